@@ -0,0 +1,47 @@
+//! Sampling and reporting shared by the benches, which run without a harness.
+
+use std::time::Duration;
+
+/// Takes `samples` timings of each contender, interleaved in alternating order so frequency
+/// scaling and cache warmth hit both alike.
+pub fn sample(
+    samples: usize,
+    mut first: impl FnMut() -> Duration,
+    mut second: impl FnMut() -> Duration,
+) -> (Vec<Duration>, Vec<Duration>) {
+    let mut first_samples = vec![];
+    let mut second_samples = vec![];
+    for sample in 0..samples {
+        for contender in 0..2 {
+            if (sample + contender) % 2 == 0 {
+                first_samples.push(first());
+            } else {
+                second_samples.push(second());
+            }
+        }
+    }
+    (first_samples, second_samples)
+}
+
+/// Prints the median sample, and the time per instruction when each sample ran `instructions`
+/// of them, and returns it.
+pub fn report(name: &str, mut samples: Vec<Duration>, instructions: Option<usize>) -> Duration {
+    samples.sort();
+    let median = samples[samples.len() / 2];
+    match instructions {
+        Some(instructions) => {
+            let per_instruction = median.as_nanos() as f64 / instructions as f64;
+            println!("{name:<20} time: {median:>10.2?}  ({per_instruction:.2} ns/instruction)");
+        }
+        None => println!("{name:<20} time: {median:>10.2?}"),
+    }
+    median
+}
+
+/// Prints how many times longer the `numerator` median took than the `denominator` one.
+pub fn ratio(label: &str, numerator: Duration, denominator: Duration) {
+    println!(
+        "{label}: {:.2}x",
+        numerator.as_secs_f64() / denominator.as_secs_f64()
+    );
+}
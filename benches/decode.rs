@@ -0,0 +1,91 @@
+//! Compares decoding instruction bytes with `OpCode::try_from` against `OpCode::decode`, which
+//! `VM::run` dispatches through. Both decode the loop's opcodes in isolation, a stand-in loop
+//! without the VM's stack or operands, so this times the decoding alone. Running the same loop
+//! in the VM is timed alongside to show how much of an instruction's cost that is. Run with
+//! `cargo bench --bench decode`.
+
+mod common;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use rlox::op_code::OpCode;
+use rlox::vm::VM;
+
+const ITERATIONS: usize = 100_000;
+const SAMPLES: usize = 21;
+
+/// The opcodes executed by one pass of `while (i < n) { x = x * 2 + i - 1; i = i + 1; }`.
+const ARITHMETIC_LOOP: [u8; 20] = [
+    OpCode::OP_GET_GLOBAL as u8,
+    OpCode::OP_GET_GLOBAL as u8,
+    OpCode::OP_LESS as u8,
+    OpCode::OP_JUMP_IF_FALSE as u8,
+    OpCode::OP_POP as u8,
+    OpCode::OP_GET_GLOBAL as u8,
+    OpCode::OP_CONSTANT as u8,
+    OpCode::OP_MULTIPLY as u8,
+    OpCode::OP_GET_GLOBAL as u8,
+    OpCode::OP_ADD as u8,
+    OpCode::OP_CONSTANT as u8,
+    OpCode::OP_SUBTRACT as u8,
+    OpCode::OP_SET_GLOBAL as u8,
+    OpCode::OP_POP as u8,
+    OpCode::OP_GET_GLOBAL as u8,
+    OpCode::OP_CONSTANT as u8,
+    OpCode::OP_ADD as u8,
+    OpCode::OP_SET_GLOBAL as u8,
+    OpCode::OP_POP as u8,
+    OpCode::OP_LOOP as u8,
+];
+
+fn main() {
+    let code: Vec<u8> = ARITHMETIC_LOOP
+        .iter()
+        .cycle()
+        .take(ARITHMETIC_LOOP.len() * ITERATIONS)
+        .copied()
+        .collect();
+
+    let (try_from, decode) = common::sample(
+        SAMPLES,
+        || decode_all(&code, |byte| OpCode::try_from(byte).unwrap()),
+        || decode_all(&code, |byte| OpCode::decode(byte).unwrap()),
+    );
+
+    let try_from = common::report("decode/try_from", try_from, Some(code.len()));
+    let decode = common::report("decode/decode", decode, Some(code.len()));
+    common::ratio("speedup", try_from, decode);
+
+    let source = format!(
+        "var i = 0; var n = {ITERATIONS}; var x = 0;
+         while (i < n) {{ x = x * 2 + i - 1; i = i + 1; }}"
+    );
+    let run = (0..SAMPLES).map(|_| run(&source)).collect();
+    common::report("vm/run", run, Some(code.len()));
+}
+
+fn run(source: &str) -> Duration {
+    let mut vm = VM::new();
+    let start = Instant::now();
+    vm.interpret(source.as_bytes().to_vec()).unwrap();
+    start.elapsed()
+}
+
+/// Decodes every byte of `code` and matches on it like `VM::run` does, with a counter standing in
+/// for the work of each instruction.
+fn decode_all(code: &[u8], decode: impl Fn(u8) -> OpCode) -> Duration {
+    let start = Instant::now();
+    let mut acc = 0u64;
+    for &byte in black_box(code) {
+        acc = match decode(black_box(byte)) {
+            OpCode::OP_ADD => acc.wrapping_add(1),
+            OpCode::OP_SUBTRACT => acc.wrapping_sub(1),
+            OpCode::OP_MULTIPLY => acc.wrapping_mul(3),
+            OpCode::OP_LOOP => acc >> 1,
+            _ => acc,
+        };
+    }
+    black_box(acc);
+    start.elapsed()
+}
@@ -0,0 +1,49 @@
+//! Times a loop reading a global against the same loop reading a local. `OP_GET_GLOBAL` caches
+//! the slot it resolves, which should keep the global loop close to the local one. Run with
+//! `cargo bench --bench globals`.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use rlox::vm::VM;
+
+const SAMPLES: usize = 11;
+
+const GLOBAL_LOOP: &str = "
+var x = 1;
+var i = 0;
+var sum = 0;
+while (i < 100000) {
+  sum = sum + x + x + x + x;
+  i = i + 1;
+}
+";
+
+const LOCAL_LOOP: &str = "
+{
+  var x = 1;
+  var i = 0;
+  var sum = 0;
+  while (i < 100000) {
+    sum = sum + x + x + x + x;
+    i = i + 1;
+  }
+}
+";
+
+fn main() {
+    let (global, local) =
+        common::sample(SAMPLES, || interpret(GLOBAL_LOOP), || interpret(LOCAL_LOOP));
+
+    let global = common::report("globals/global", global, None);
+    let local = common::report("globals/local", local, None);
+    common::ratio("global/local", global, local);
+}
+
+fn interpret(source: &str) -> Duration {
+    let mut vm = VM::new();
+    let start = Instant::now();
+    vm.interpret(source.as_bytes().to_vec()).unwrap();
+    start.elapsed()
+}
@@ -1,74 +1,1072 @@
-use std::ops::Sub;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::{Range, Sub};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
+use crate::object::{Function, Object, ObjectType};
 use crate::op_code::OpCode;
 use crate::value::{Value, ValueArray};
 
 pub type Instruction = u8;
 
-#[derive(Default)]
+#[derive(Debug, Default)]
 pub struct Chunk {
     pub(crate) code: Vec<Instruction>,
-    count: usize,
     pub(crate) constants: ValueArray,
     pub(crate) lines: Vec<usize>,
+    /// source range of every byte, only kept once `record_spans` is called
+    pub(crate) spans: Option<Vec<Range<usize>>>,
+    /// global slot resolved for each constant the first time `OP_GET_GLOBAL` named it
+    pub(crate) global_cache: GlobalCache,
+    /// index of every number constant by its bit pattern, so each number is stored once
+    numbers: HashMap<u32, usize>,
+    /// length of the code when each constant was added, so `drop_constants` can tell which ones
+    /// came with code that was truncated
+    constant_offsets: Vec<usize>,
+}
+
+/// Chunks compare by their code, lines and constants. Spans and the lookup tables built while
+/// writing and running them don't take part, so a chunk compiled with a source map equals one
+/// compiled without, and the order constants were added in doesn't matter.
+///
+/// Function constants compare by identity, so only chunks without them can equal a separate
+/// compilation.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code && self.lines == other.lines && self.constants == other.constants
+    }
+}
+
+/// Filled in while the chunk runs, so it doesn't take part in comparing chunks.
+#[derive(Debug, Default)]
+pub(crate) struct GlobalCache(Vec<Cell<Option<usize>>>);
+
+impl GlobalCache {
+    pub fn get(&self, constant: usize) -> Option<&Cell<Option<usize>>> {
+        self.0.get(constant)
+    }
 }
 
 impl Chunk {
     pub fn write(&mut self, data: u8, line: usize) {
-        if self.code.len() < self.count + 1 {
-            self.code.push(data);
-            self.lines.push(line);
-        } else {
-            self.code[self.count] = data;
-            self.lines[self.count] = line;
+        self.write_with_span(data, line, 0..0);
+    }
+
+    /// Like `write`, also keeping the source range the byte was compiled from if spans are
+    /// being recorded.
+    pub fn write_with_span(&mut self, data: u8, line: usize, span: Range<usize>) {
+        self.code.push(data);
+        self.lines.push(line);
+        if let Some(spans) = &mut self.spans {
+            spans.push(span);
         }
+    }
 
-        self.count += 1;
+    /// Keeps a source span for every byte written from now on, for tooling that points at the
+    /// exact expression an instruction came from. Bytes written before have none.
+    pub fn record_spans(&mut self) {
+        let written = self.code.len();
+        self.spans.get_or_insert_with(|| vec![0..0; written]);
+    }
+
+    /// Source range the byte at `offset` was compiled from, `None` when it wasn't recorded.
+    pub fn span_of(&self, offset: usize) -> Option<Range<usize>> {
+        let span = self.spans.as_ref()?.get(offset)?;
+        (!span.is_empty()).then(|| span.clone())
+    }
+
+    /// Drops everything written from `len` on. Constants stay, see `drop_constants`.
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.lines.truncate(len);
+        if let Some(spans) = &mut self.spans {
+            spans.truncate(len);
+        }
+    }
+
+    /// Drops the constants in `loaded` that were added once the code was `since` long, for code
+    /// that loaded them and was truncated. Others added since, like the name a folded initializer
+    /// is assigned to, are still wanted, and only constants after the last of those can go.
+    pub fn drop_constants(&mut self, loaded: &[usize], since: usize) {
+        while let Some(&offset) = self.constant_offsets.last() {
+            let constant = self.constant_offsets.len() - 1;
+            if offset < since || !loaded.contains(&constant) {
+                break;
+            }
+            self.constant_offsets.pop();
+        }
+
+        let constants = self.constant_offsets.len();
+        self.constants.values.truncate(constants);
+        self.global_cache.0.truncate(constants);
+        self.numbers.retain(|_, &mut constant| constant < constants);
+    }
+
+    /// Writes a forward jump with a placeholder distance, returning the offset of its operand to
+    /// hand to `patch_jump` once the target is known.
+    pub fn emit_jump(&mut self, op_code: OpCode, line: usize) -> usize {
+        self.write(op_code.into(), line);
+        self.write(0xff, line);
+        self.write(0xff, line);
+        self.code.len() - 2
+    }
+
+    /// Points the jump whose operand is at `offset` at the end of the chunk.
+    pub fn patch_jump(&mut self, offset: usize) -> Result<()> {
+        // the distance is counted from past the operand
+        let jump = self.code.len() - offset - 2;
+        let jump = u16::try_from(jump).map_err(|_| anyhow!("Too much code to jump over."))?;
+
+        self.code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+        Ok(())
     }
 
+    /// Number of bytes written so far.
+    pub fn count(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Source line of the byte at `offset`, `None` past the end of the chunk.
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        self.lines.get(offset).copied()
+    }
+
+    /// Empties the chunk so it can be written again, keeping its allocations.
+    pub fn free(&mut self) {
+        self.code.clear();
+        self.lines.clear();
+        if let Some(spans) = &mut self.spans {
+            spans.clear();
+        }
+        self.constants = ValueArray::default();
+        self.global_cache = GlobalCache::default();
+        self.numbers.clear();
+        self.constant_offsets.clear();
+    }
+
+    /// Numbers are interned by their exact bits: `1.0` is stored once however often it's added,
+    /// while `0.0` and `-0.0` stay apart. A NaN is only reused for a NaN with the same bits, and
+    /// since it never equals itself, that's the only way to find it again anyway.
     pub fn add_constant(&mut self, value: Value) -> usize {
+        if let Value::VAL_NUMBER(number) = value {
+            if let Some(&constant) = self.numbers.get(&number.to_bits()) {
+                return constant;
+            }
+            self.numbers.insert(number.to_bits(), self.constants.len());
+        }
+
         self.constants.write(value);
-        self.constants.count - 1
+        self.constant_offsets.push(self.code.len());
+        self.global_cache.0.push(Cell::new(None));
+        self.constants.len() - 1
     }
 
-    pub fn disassemble_chunk(&self, name: String) -> Result<()> {
-        println!("==== {name:<8}  ====");
+    /// Builds a chunk from an assembly listing with one instruction per line, `#` starts a comment:
+    ///
+    /// ```text
+    /// OP_CONSTANT 1.5        # numbers, strings, true, false and nil go in the constant pool
+    /// OP_GET_GLOBAL "name"
+    /// print 1                # byte operands are integers, opcodes can use their display name
+    /// OP_INVOKE "method" 2
+    /// OP_LOOP 7              # jumps take the raw offset
+    /// ```
+    ///
+    /// Each instruction gets the line it's written on. `OP_CLOSURE` needs a function constant and
+    /// can't be assembled. The chunk is verified before it's returned.
+    pub fn assemble(text: &str) -> Result<Chunk> {
+        let mut chunk = Chunk::default();
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            chunk
+                .assemble_line(strip_comment(line).trim(), line_number)
+                .map_err(|e| anyhow!("line {line_number}: {e}"))?;
+        }
+
+        chunk.verify()?;
+        Ok(chunk)
+    }
+
+    fn assemble_line(&mut self, line: &str, line_number: usize) -> Result<()> {
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let (name, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let operands = operands.trim();
+        let op_code = OpCode::from_name(name).ok_or_else(|| anyhow!("unknown opcode '{name}'"))?;
+
+        let operand_bytes = match op_code {
+            OpCode::OP_CLOSURE => return Err(anyhow!("{op_code:?} can't be assembled")),
+            OpCode::OP_INVOKE | OpCode::OP_TAIL_INVOKE | OpCode::OP_SUPER_INVOKE => {
+                let (name, arg_count) = operands
+                    .rsplit_once(char::is_whitespace)
+                    .ok_or_else(|| anyhow!("{op_code:?} takes a name and an argument count"))?;
+                vec![self.assemble_constant(name.trim())?, parse_byte(arg_count)?]
+            }
+            OpCode::OP_JUMP_IF_FALSE | OpCode::OP_JUMP | OpCode::OP_ITER_NEXT | OpCode::OP_LOOP => {
+                let jump = operands
+                    .parse::<u16>()
+                    .map_err(|_| anyhow!("invalid jump offset '{operands}'"))?;
+                jump.to_be_bytes().to_vec()
+            }
+            OpCode::OP_CONSTANT
+            | OpCode::OP_DEFINE_GLOBAL
+            | OpCode::OP_DEFINE_CONST_GLOBAL
+            | OpCode::OP_GET_GLOBAL
+            | OpCode::OP_SET_GLOBAL
+            | OpCode::OP_CLASS
+            | OpCode::OP_GET_PROPERTY
+            | OpCode::OP_SET_PROPERTY
+            | OpCode::OP_METHOD
+            | OpCode::OP_GET_SUPER => vec![self.assemble_constant(operands)?],
+            _ if op_code.operand_len() == 1 => vec![parse_byte(operands)?],
+            _ if operands.is_empty() => vec![],
+            _ => return Err(anyhow!("{op_code:?} takes no operands")),
+        };
+
+        self.write(op_code.into(), line_number);
+        for byte in operand_bytes {
+            self.write(byte, line_number);
+        }
+        Ok(())
+    }
+
+    /// Adds a literal operand to the constant pool, returning its index.
+    fn assemble_constant(&mut self, operand: &str) -> Result<u8> {
+        let value = match operand {
+            "true" => Value::VAL_BOOL(true),
+            "false" => Value::VAL_BOOL(false),
+            "nil" => Value::VAL_NIL,
+            _ if operand.len() >= 2 && operand.starts_with('"') && operand.ends_with('"') => {
+                Value::VAL_OBJECT(Object {
+                    object_type: ObjectType::OBJ_STRING(operand[1..operand.len() - 1].into()),
+                })
+            }
+            _ => Value::VAL_NUMBER(
+                operand
+                    .parse()
+                    .map_err(|_| anyhow!("invalid constant '{operand}'"))?,
+            ),
+        };
+
+        let constant = self.add_constant(value);
+        u8::try_from(constant).map_err(|_| anyhow!("too many constants in one chunk"))
+    }
+
+    /// Checks that running the chunk as a script can't read past its code, constants or stack:
+    /// every opcode is valid and has its operands, every constant index is in range and of the
+    /// kind its instruction needs, every jump lands on the start of an instruction, and no
+    /// instruction pops or reads a local below the values pushed before it. Functions among the
+    /// constants are verified too.
+    pub fn verify(&self) -> Result<()> {
+        // the script runs without a callee on the stack and its return leaves the stack alone
+        self.verify_code(&[(0, 0)], 0)
+    }
+
+    /// Verifies the code of a function or script, which calls start at one of `entries` with the
+    /// given number of values in their frame. Returning pops `returned` values.
+    fn verify_code(&self, entries: &[(usize, usize)], returned: usize) -> Result<()> {
+        let mut boundaries = vec![false; self.code.len()];
+        let mut jumps = vec![];
 
         let mut offset = 0;
-        while offset < self.count {
-            offset = self.disassemble_instruction(offset)?;
+        while offset < self.code.len() {
+            boundaries[offset] = true;
+            let instruction = self.code[offset];
+            let op_code = OpCode::try_from(instruction)
+                .map_err(|_| anyhow!("invalid opcode {instruction} at offset {offset}"))?;
+
+            let mut next = offset + 1 + op_code.operand_len();
+            if next > self.code.len() {
+                return Err(anyhow!("{op_code:?} at offset {offset} is truncated"));
+            }
+
+            match op_code {
+                OpCode::OP_CONSTANT => {
+                    self.verify_constant(offset)?;
+                }
+                OpCode::OP_DEFINE_GLOBAL
+                | OpCode::OP_DEFINE_CONST_GLOBAL
+                | OpCode::OP_GET_GLOBAL
+                | OpCode::OP_SET_GLOBAL
+                | OpCode::OP_CLASS
+                | OpCode::OP_GET_PROPERTY
+                | OpCode::OP_SET_PROPERTY
+                | OpCode::OP_METHOD
+                | OpCode::OP_GET_SUPER
+                | OpCode::OP_INVOKE
+                | OpCode::OP_TAIL_INVOKE
+                | OpCode::OP_SUPER_INVOKE => {
+                    if self.verify_constant(offset)?.as_string().is_none() {
+                        return Err(anyhow!(
+                            "{op_code:?} at offset {offset} needs a string constant"
+                        ));
+                    }
+                }
+                OpCode::OP_CLOSURE => {
+                    let function = self.closure_function(offset)?;
+                    next += 2 * function.upvalue_count;
+                    if next > self.code.len() {
+                        return Err(anyhow!("{op_code:?} at offset {offset} is truncated"));
+                    }
+                }
+                OpCode::OP_JUMP_IF_FALSE | OpCode::OP_JUMP | OpCode::OP_ITER_NEXT => {
+                    jumps.push((offset, next.checked_add(self.read_jump(offset))));
+                }
+                OpCode::OP_LOOP => jumps.push((offset, next.checked_sub(self.read_jump(offset)))),
+                _ => {}
+            }
+            offset = next;
+        }
+
+        for (offset, target) in jumps {
+            if !target.is_some_and(|target| boundaries.get(target) == Some(&true)) {
+                return Err(anyhow!(
+                    "jump at offset {offset} doesn't land on an instruction"
+                ));
+            }
         }
+        self.verify_stack(entries, returned)?;
 
+        for value in self.constants.iter() {
+            if let Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_FUNCTION(function),
+            }) = value
+            {
+                // a call passing fewer arguments than the parameters starts at a default
+                let min_arity = function.min_arity();
+                let mut entries = function
+                    .defaults
+                    .iter()
+                    .enumerate()
+                    .map(|(given, &entry)| (entry, min_arity + given + 1))
+                    .collect::<Vec<_>>();
+                entries.push((function.body, function.arity + 1));
+                function.chunk.verify_code(&entries, 1)?;
+            }
+        }
         Ok(())
     }
 
+    /// Follows every path from the entries, tracking the fewest values the frame can hold at each
+    /// instruction, and fails if an instruction can take more than that. Only called once the
+    /// code and its jumps have been checked.
+    fn verify_stack(&self, entries: &[(usize, usize)], returned: usize) -> Result<()> {
+        let mut depths: Vec<Option<usize>> = vec![None; self.code.len()];
+        let mut pending = entries.to_vec();
+
+        while let Some((offset, depth)) = pending.pop() {
+            let Some(known) = depths.get_mut(offset) else {
+                return Err(anyhow!("execution runs past the end of the chunk"));
+            };
+            if known.is_some_and(|known| known <= depth) {
+                continue;
+            }
+            *known = Some(depth);
+
+            let op_code = OpCode::decode(self.code[offset])?;
+            let byte = |index: usize| self.code[offset + index] as usize;
+            let (popped, pushed) = match op_code {
+                OpCode::OP_CONSTANT
+                | OpCode::OP_TRUE
+                | OpCode::OP_FALSE
+                | OpCode::OP_NIL
+                | OpCode::OP_GET_GLOBAL
+                | OpCode::OP_CLASS
+                | OpCode::OP_CLOSURE
+                | OpCode::OP_GET_LOCAL
+                | OpCode::OP_GET_UPVALUE => (0, 1),
+                OpCode::OP_NEGATE
+                | OpCode::OP_NOT
+                | OpCode::OP_TO_STRING
+                | OpCode::OP_GET_PROPERTY
+                | OpCode::OP_GET_SUPER
+                | OpCode::OP_JUMP_IF_FALSE
+                | OpCode::OP_SET_GLOBAL
+                | OpCode::OP_SET_LOCAL
+                | OpCode::OP_SET_UPVALUE => (1, 1),
+                OpCode::OP_ADD
+                | OpCode::OP_SUBTRACT
+                | OpCode::OP_MULTIPLY
+                | OpCode::OP_DIVIDE
+                | OpCode::OP_EQUAL
+                | OpCode::OP_GREATER
+                | OpCode::OP_LESS
+                | OpCode::OP_INDEX_GET
+                | OpCode::OP_IS
+                | OpCode::OP_RANGE
+                | OpCode::OP_SET_PROPERTY
+                | OpCode::OP_METHOD => (2, 1),
+                OpCode::OP_POP
+                | OpCode::OP_DEFINE_GLOBAL
+                | OpCode::OP_DEFINE_CONST_GLOBAL
+                | OpCode::OP_CLOSE_UPVALUE => (1, 0),
+                OpCode::OP_ASSERT | OpCode::OP_INHERIT => (2, 0),
+                OpCode::OP_INDEX_SET => (3, 1),
+                OpCode::OP_JUMP | OpCode::OP_LOOP => (0, 0),
+                OpCode::OP_PRINT | OpCode::OP_POPN => (byte(1), 0),
+                OpCode::OP_BUILD_LIST => (byte(1), 1),
+                OpCode::OP_BUILD_MAP => (2 * byte(1), 1),
+                OpCode::OP_CALL | OpCode::OP_TAIL_CALL => (byte(1) + 1, 1),
+                OpCode::OP_INVOKE | OpCode::OP_TAIL_INVOKE | OpCode::OP_SUPER_INVOKE => {
+                    (byte(2) + 1, 1)
+                }
+                // the iterated value and the index stay, the item is pushed unless the loop ends
+                OpCode::OP_ITER_NEXT => (2, 2),
+                OpCode::OP_RETURN => (returned, 0),
+            };
+            if popped > depth {
+                return Err(anyhow!(
+                    "{op_code:?} at offset {offset} pops more values than the stack holds"
+                ));
+            }
+            if matches!(op_code, OpCode::OP_GET_LOCAL | OpCode::OP_SET_LOCAL) && byte(1) >= depth {
+                return Err(anyhow!(
+                    "{op_code:?} at offset {offset} reads slot {} past the stack",
+                    byte(1)
+                ));
+            }
+            let depth = depth - popped + pushed;
+
+            let mut next = offset + 1 + op_code.operand_len();
+            match op_code {
+                OpCode::OP_CLOSURE => {
+                    let function = self.closure_function(offset)?;
+                    for upvalue in 0..function.upvalue_count {
+                        let (is_local, index) = (byte(2 + 2 * upvalue), byte(3 + 2 * upvalue));
+                        if is_local == 1 && index >= depth {
+                            return Err(anyhow!(
+                                "{op_code:?} at offset {offset} captures slot {index} past the stack"
+                            ));
+                        }
+                    }
+                    next += 2 * function.upvalue_count;
+                }
+                OpCode::OP_RETURN => continue,
+                OpCode::OP_JUMP => {
+                    pending.push((next + self.read_jump(offset), depth));
+                    continue;
+                }
+                OpCode::OP_LOOP => {
+                    pending.push((next - self.read_jump(offset), depth));
+                    continue;
+                }
+                OpCode::OP_JUMP_IF_FALSE => pending.push((next + self.read_jump(offset), depth)),
+                OpCode::OP_ITER_NEXT => {
+                    pending.push((next + self.read_jump(offset), depth));
+                    pending.push((next, depth + 1));
+                    continue;
+                }
+                _ => {}
+            }
+            pending.push((next, depth));
+        }
+        Ok(())
+    }
+
+    /// Returns the constant named by the operand of the instruction at `offset`.
+    fn verify_constant(&self, offset: usize) -> Result<&Value> {
+        let constant = self.code[offset + 1];
+        self.constants
+            .get(constant as usize)
+            .ok_or_else(|| anyhow!("constant {constant} at offset {offset} is out of range"))
+    }
+
+    /// Returns the function the `OP_CLOSURE` at `offset` creates a closure of.
+    fn closure_function(&self, offset: usize) -> Result<&Function> {
+        match self.verify_constant(offset)? {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_FUNCTION(function),
+            }) => Ok(function),
+            _ => Err(anyhow!(
+                "OP_CLOSURE at offset {offset} needs a function constant"
+            )),
+        }
+    }
+
+    fn read_jump(&self, offset: usize) -> usize {
+        ((self.code[offset + 1] as usize) << 8) | self.code[offset + 2] as usize
+    }
+
+    pub fn disassemble_chunk(&self, name: String) -> Result<()> {
+        print!("{}", self.disassemble_to_string(&name)?);
+        Ok(())
+    }
+
+    /// Renders the whole chunk the same way `disassemble_chunk` prints it.
+    pub fn disassemble_to_string(&self, name: &str) -> Result<String> {
+        let mut out = format!("==== {name:<8}  ====\n");
+
+        let mut offset = 0;
+        while offset < self.count() {
+            offset = self.write_instruction(&mut out, offset)?;
+        }
+
+        Ok(out)
+    }
+
     pub(crate) fn disassemble_instruction(&self, offset: usize) -> Result<usize> {
-        print!("{offset:04} ");
-        if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("   | ");
-        } else {
-            print!("{:4} ", self.lines[offset]);
+        let mut out = String::new();
+        let offset = self.write_instruction(&mut out, offset)?;
+        print!("{out}");
+        Ok(offset)
+    }
+
+    /// Renders the instruction that the byte at `offset` is part of like `disassemble_instruction`,
+    /// except that its line is always shown.
+    pub(crate) fn instruction_containing(&self, offset: usize) -> Option<String> {
+        let mut start = 0;
+        while start <= offset {
+            let mut out = String::new();
+            let next = self.write_operation(&mut out, start).ok()?;
+            if offset < next {
+                let line = self.line_of(start)?;
+                return Some(format!("{start:04} {line:4} {out}"));
+            }
+            start = next;
+        }
+        None
+    }
+
+    /// Writes the instruction at `offset` the way `disassemble_instruction` prints it, returning
+    /// the offset of the next one.
+    pub(crate) fn write_instruction(&self, out: &mut String, offset: usize) -> Result<usize> {
+        let Some(&instruction) = self.code.get(offset) else {
+            return Err(anyhow!("offset {offset} is past the end of the chunk"));
+        };
+
+        write!(out, "{offset:04} ")?;
+        match self.line_of(offset) {
+            Some(line) if offset > 0 && self.line_of(offset - 1) == Some(line) => {
+                write!(out, "   | ")?
+            }
+            Some(line) => write!(out, "{line:4} ")?,
+            None => write!(out, "   ? ")?,
+        }
+        self.write_operation(out, offset)
+    }
+
+    /// Writes the instruction at `offset` without its offset and line, returning the offset of
+    /// the next one.
+    fn write_operation(&self, out: &mut String, offset: usize) -> Result<usize> {
+        let instruction = self.code[offset];
+        let op_code = OpCode::try_from(instruction)?;
+        let name = op_code.to_string();
+        let next = offset + 1 + op_code.operand_len();
+        if next > self.code.len() {
+            writeln!(out, "{name:-16} <truncated>")?;
+            return Ok(self.code.len());
         }
 
-        let op_code = OpCode::try_from(self.code[offset])?;
-        Ok(match op_code {
-            OpCode::OP_CONSTANT => self.constant_instruction("OP_CONSTANT", offset),
-            _ => Self::simple_instruction(&op_code, offset),
-        })
+        match op_code {
+            OpCode::OP_CONSTANT
+            | OpCode::OP_DEFINE_GLOBAL
+            | OpCode::OP_DEFINE_CONST_GLOBAL
+            | OpCode::OP_GET_GLOBAL
+            | OpCode::OP_SET_GLOBAL
+            | OpCode::OP_CLASS
+            | OpCode::OP_GET_PROPERTY
+            | OpCode::OP_SET_PROPERTY
+            | OpCode::OP_METHOD
+            | OpCode::OP_GET_SUPER => self.constant_instruction(out, &name, offset)?,
+            OpCode::OP_JUMP_IF_FALSE | OpCode::OP_JUMP | OpCode::OP_ITER_NEXT => {
+                self.jump_instruction(out, &name, 1, offset)?
+            }
+            OpCode::OP_LOOP => self.jump_instruction(out, &name, -1, offset)?,
+            OpCode::OP_INVOKE | OpCode::OP_TAIL_INVOKE | OpCode::OP_SUPER_INVOKE => {
+                self.invoke_instruction(out, &name, offset)?
+            }
+            OpCode::OP_CLOSURE => return self.closure_instruction(out, &name, offset),
+            _ if op_code.operand_len() == 1 => self.byte_instruction(out, &name, offset)?,
+            _ => writeln!(out, "{name}")?,
+        }
+        Ok(next)
     }
 
-    fn simple_instruction(name: &OpCode, offset: usize) -> usize {
-        println!("{name:?}");
-        offset + 1
+    fn constant_instruction(&self, out: &mut String, name: &str, offset: usize) -> Result<()> {
+        let constant = self.code[offset + 1];
+        write!(out, "{name:-16} {constant:02} ")?;
+        match self.constants.get(constant as usize) {
+            Some(value) => writeln!(out, "{value:?}")?,
+            None => writeln!(out, "<invalid constant>")?,
+        }
+        Ok(())
     }
 
-    fn constant_instruction(&self, name: &str, offset: usize) -> usize {
+    /// The function constant is followed by an `is_local`, `index` byte pair per captured variable.
+    fn closure_instruction(&self, out: &mut String, name: &str, offset: usize) -> Result<usize> {
         let constant = self.code[offset + 1];
-        print!("{name:-16} {constant:02} ");
-        print!("{:?}", self.constants.values[constant as usize]);
-        println!();
-        offset + 2
+        write!(out, "{name:-16} {constant:02} ")?;
+        let upvalue_count = match self.constants.get(constant as usize) {
+            Some(
+                value @ Value::VAL_OBJECT(Object {
+                    object_type: ObjectType::OBJ_FUNCTION(function),
+                }),
+            ) => {
+                writeln!(out, "{value:?}")?;
+                function.upvalue_count
+            }
+            Some(value) => {
+                writeln!(out, "{value:?}")?;
+                0
+            }
+            None => {
+                writeln!(out, "<invalid constant>")?;
+                0
+            }
+        };
+
+        let mut offset = offset + 2;
+        for _ in 0..upvalue_count {
+            let (Some(&is_local), Some(&index)) =
+                (self.code.get(offset), self.code.get(offset + 1))
+            else {
+                writeln!(out, "{offset:04}    | <truncated>")?;
+                return Ok(self.code.len());
+            };
+
+            let kind = if is_local == 1 { "local" } else { "upvalue" };
+            writeln!(out, "{offset:04}    |                  {kind} {index}")?;
+            offset += 2;
+        }
+        Ok(offset)
+    }
+
+    fn invoke_instruction(&self, out: &mut String, name: &str, offset: usize) -> Result<()> {
+        let (constant, arg_count) = (self.code[offset + 1], self.code[offset + 2]);
+        write!(out, "{name:-16} ({arg_count} args) {constant:02} ")?;
+        match self.constants.get(constant as usize) {
+            Some(value) => writeln!(out, "{value:?}")?,
+            None => writeln!(out, "<invalid constant>")?,
+        }
+        Ok(())
+    }
+
+    fn byte_instruction(&self, out: &mut String, name: &str, offset: usize) -> Result<()> {
+        let operand = self.code[offset + 1];
+        writeln!(out, "{name:-16} {operand:4}")?;
+        Ok(())
+    }
+
+    fn jump_instruction(
+        &self,
+        out: &mut String,
+        name: &str,
+        sign: isize,
+        offset: usize,
+    ) -> Result<()> {
+        let (high, low) = (self.code[offset + 1], self.code[offset + 2]);
+        let jump = ((high as u16) << 8) | low as u16;
+        let target = offset as isize + 3 + sign * jump as isize;
+        writeln!(out, "{name:-16} {offset:4} -> {target}")?;
+        Ok(())
+    }
+}
+
+/// Cuts `line` off at a `#` outside of a string literal.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_byte(operand: &str) -> Result<u8> {
+    operand
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("invalid byte operand '{operand}'"))
+}
+
+/// Writes a chunk one instruction at a time, for comparing against compiled ones in tests.
+#[cfg(test)]
+pub(crate) struct ChunkBuilder {
+    chunk: Chunk,
+    line: usize,
+}
+
+#[cfg(test)]
+impl ChunkBuilder {
+    pub fn new() -> Self {
+        Self {
+            chunk: Chunk::default(),
+            line: 1,
+        }
+    }
+
+    /// Line of the instructions written after this.
+    pub fn line(mut self, line: usize) -> Self {
+        self.line = line;
+        self
+    }
+
+    pub fn op(mut self, op_code: OpCode) -> Self {
+        self.chunk.write(op_code.into(), self.line);
+        self
+    }
+
+    pub fn operand(mut self, operand: u8) -> Self {
+        self.chunk.write(operand, self.line);
+        self
+    }
+
+    /// Adds `value` to the constants and loads it with `OP_CONSTANT`.
+    pub fn constant(mut self, value: Value) -> Self {
+        let constant = self.chunk.add_constant(value) as u8;
+        self.op(OpCode::OP_CONSTANT).operand(constant)
+    }
+
+    pub fn build(self) -> Chunk {
+        self.chunk
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn disassemble_truncated_operand_should_succeed() {
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::OP_CONSTANT.into(), 1);
+
+        assert_eq!(chunk.disassemble_instruction(0).unwrap(), 1);
+        assert!(chunk.disassemble_chunk("truncated".to_string()).is_ok());
+        assert!(chunk.disassemble_instruction(1).is_err());
+
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::OP_LOOP.into(), 1);
+        chunk.write(0, 1);
+
+        assert_eq!(chunk.disassemble_instruction(0).unwrap(), 2);
+    }
+
+    #[test]
+    fn add_constant_should_succeed() {
+        let mut chunk = Chunk::default();
+        for _ in 0..3 {
+            assert_eq!(chunk.add_constant(Value::VAL_NUMBER(1.0)), 0);
+        }
+        assert_eq!(chunk.constants.len(), 1);
+
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(0.0)), 1);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(-0.0)), 2);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(f32::NAN)), 3);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(f32::NAN)), 3);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(-f32::NAN)), 4);
+        // only numbers are interned
+        assert_eq!(chunk.add_constant(Value::VAL_NIL), 5);
+        assert_eq!(chunk.add_constant(Value::VAL_NIL), 6);
+
+        // constants loaded by the truncated code go with it, earlier and unrelated ones stay
+        chunk.write(OpCode::OP_NIL.into(), 1);
+        assert_eq!(chunk.add_constant(Value::VAL_NIL), 7);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(2.0)), 8);
+        chunk.write(OpCode::OP_NIL.into(), 1);
+        chunk.truncate(1);
+        chunk.drop_constants(&[0, 8], 1);
+        assert_eq!(chunk.constants.len(), 8);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(1.0)), 0);
+        assert_eq!(chunk.add_constant(Value::VAL_NUMBER(2.0)), 8);
+    }
+
+    #[test]
+    fn equality_should_succeed() {
+        let mut constant_first = Chunk::default();
+        let constant = constant_first.add_constant(Value::VAL_NUMBER(1.0));
+        constant_first.write(OpCode::OP_CONSTANT.into(), 1);
+        constant_first.write(constant as u8, 1);
+
+        let mut code_first = Chunk::default();
+        code_first.write(OpCode::OP_CONSTANT.into(), 1);
+        code_first.write(0, 1);
+        code_first.add_constant(Value::VAL_NUMBER(1.0));
+
+        assert_eq!(constant_first, code_first);
+
+        constant_first.record_spans();
+        constant_first.write_with_span(OpCode::OP_RETURN.into(), 1, 0..1);
+        code_first.write(OpCode::OP_RETURN.into(), 1);
+        assert_eq!(constant_first, code_first);
+
+        code_first.write(OpCode::OP_RETURN.into(), 1);
+        assert_ne!(constant_first, code_first);
+    }
+
+    #[test]
+    fn fold_interned_constant_should_succeed() {
+        // `2` is shared with the first print, folding `1 + 2` must only drop the `1`
+        let chunk = Compiler::compile_to_chunk("print 2; print 1 + 2; print 2;").unwrap();
+
+        assert_eq!(
+            chunk.constants.values,
+            [Value::VAL_NUMBER(2.0), Value::VAL_NUMBER(3.0)]
+        );
+        assert_eq!(
+            chunk.code,
+            [
+                OpCode::OP_CONSTANT.into(),
+                0,
+                OpCode::OP_PRINT.into(),
+                1,
+                OpCode::OP_CONSTANT.into(),
+                1,
+                OpCode::OP_PRINT.into(),
+                1,
+                OpCode::OP_CONSTANT.into(),
+                0,
+                OpCode::OP_PRINT.into(),
+                1,
+                OpCode::OP_RETURN.into(),
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_named_constant_should_succeed() {
+        // the name is added where the folded initializer starts, and must outlive the fold
+        for (source, folded) in [
+            ("var a = 1 + 2;", 3.0),
+            ("var b = -5;", -5.0),
+            ("var z = 1 + 2 + 3;", 6.0),
+            ("var a; a = 1 + 2;", 3.0),
+            ("const C = 2 * 3;", 6.0),
+            ("var p; p.x = 1 + 2;", 3.0),
+        ] {
+            let chunk = Compiler::compile_to_chunk(source).unwrap();
+
+            assert!(chunk.verify().is_ok(), "{source}");
+            assert_eq!(
+                chunk.constants.values.last(),
+                Some(&Value::VAL_NUMBER(folded)),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn patch_jump_should_succeed() {
+        let mut chunk = Chunk::default();
+        let operand = chunk.emit_jump(OpCode::OP_JUMP, 1);
+        assert_eq!(operand, 1);
+        assert_eq!(chunk.code, [OpCode::OP_JUMP.into(), 0xff, 0xff]);
+
+        chunk.write(OpCode::OP_NIL.into(), 2);
+        chunk.write(OpCode::OP_POP.into(), 2);
+        chunk.patch_jump(operand).unwrap();
+
+        // lands past the skipped instructions
+        assert_eq!(chunk.code[1..3], [0, 2]);
+        assert_eq!(chunk.lines, [1, 1, 1, 2, 2]);
+        chunk.write(OpCode::OP_RETURN.into(), 3);
+        assert!(chunk.verify().is_ok());
+    }
+
+    #[test]
+    fn patch_jump_should_fail() {
+        let mut chunk = Chunk::default();
+        let operand = chunk.emit_jump(OpCode::OP_JUMP_IF_FALSE, 1);
+        for _ in 0..=u16::MAX {
+            chunk.write(OpCode::OP_NIL.into(), 1);
+        }
+
+        let error = chunk.patch_jump(operand).unwrap_err();
+        assert_eq!(error.to_string(), "Too much code to jump over.");
+    }
+
+    #[test]
+    fn line_of_should_succeed() {
+        let mut chunk = Chunk::default();
+        let constant = chunk.add_constant(Value::VAL_NUMBER(1.)) as u8;
+        chunk.write(OpCode::OP_CONSTANT.into(), 1);
+        chunk.write(constant, 1);
+        chunk.write(OpCode::OP_NEGATE.into(), 2);
+        chunk.write(OpCode::OP_RETURN.into(), 3);
+
+        assert_eq!(chunk.line_of(0), Some(1));
+        assert_eq!(chunk.line_of(1), Some(1));
+        assert_eq!(chunk.line_of(2), Some(2));
+        assert_eq!(chunk.line_of(3), Some(3));
+    }
+
+    #[test]
+    fn line_of_should_fail() {
+        let mut chunk = Chunk::default();
+        assert_eq!(chunk.line_of(0), None);
+
+        chunk.write(OpCode::OP_RETURN.into(), 1);
+        assert_eq!(chunk.line_of(1), None);
+    }
+
+    #[test]
+    fn free_should_succeed() {
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::OP_NIL.into(), 1);
+        chunk.write(OpCode::OP_RETURN.into(), 1);
+        chunk.add_constant(Value::VAL_NUMBER(1.));
+
+        chunk.free();
+        assert_eq!(chunk.count(), 0);
+
+        // a stale count used to index past the emptied code and lines
+        chunk.write(OpCode::OP_TRUE.into(), 2);
+        let constant = chunk.add_constant(Value::VAL_BOOL(false));
+
+        assert_eq!(chunk.count(), 1);
+        assert_eq!(chunk.code, [OpCode::OP_TRUE.into()]);
+        assert_eq!(chunk.lines, [2]);
+        assert_eq!(constant, 0);
+        assert_eq!(chunk.constants[constant], Value::VAL_BOOL(false));
+    }
+
+    #[test]
+    fn disassemble_to_string_should_succeed() {
+        let mut chunk = Chunk::default();
+        let constant = chunk.add_constant(Value::VAL_NUMBER(1.));
+        chunk.write(OpCode::OP_CONSTANT.into(), 1);
+        chunk.write(constant as u8, 1);
+        chunk.write(OpCode::OP_PRINT.into(), 2);
+        chunk.write(1, 2);
+        chunk.write(OpCode::OP_RETURN.into(), 2);
+
+        assert_eq!(
+            chunk.disassemble_to_string("test").unwrap(),
+            "==== test      ====\n\
+             0000    1 constant         00 VAL_NUMBER(1.0)\n\
+             0002    2 print               1\n\
+             0004    | return\n"
+        );
+    }
+
+    #[test]
+    fn verify_should_succeed() {
+        let mut chunk = Chunk::default();
+        let source = r#"
+            var total = 0;
+            fun add(n) {
+                var i = 0;
+                while (i < n) { total = total + i; i = i + 1; }
+                fun get() { return total; }
+                return get;
+            }
+            print add(3)();
+        "#;
+        assert!(Compiler::new(&mut chunk).compile(source.to_string().into_bytes()));
+
+        assert!(chunk.verify().is_ok());
+    }
+
+    #[test]
+    fn verify_should_fail() {
+        let mut chunk = Chunk::default();
+        chunk.add_constant(Value::VAL_NUMBER(1.));
+        chunk.write(OpCode::OP_CONSTANT.into(), 1);
+        chunk.write(1, 1);
+        chunk.write(OpCode::OP_RETURN.into(), 1);
+
+        assert_eq!(
+            chunk.verify().unwrap_err().to_string(),
+            "constant 1 at offset 0 is out of range"
+        );
+
+        // jumps into the constant operand
+        let mut chunk = Chunk::default();
+        chunk.add_constant(Value::VAL_NUMBER(1.));
+        chunk.write(OpCode::OP_CONSTANT.into(), 1);
+        chunk.write(0, 1);
+        chunk.write(OpCode::OP_LOOP.into(), 1);
+        chunk.write(0, 1);
+        chunk.write(4, 1);
+
+        assert_eq!(
+            chunk.verify().unwrap_err().to_string(),
+            "jump at offset 2 doesn't land on an instruction"
+        );
+
+        let mut chunk = Chunk::default();
+        chunk.write(u8::MAX, 1);
+
+        assert!(chunk.verify().is_err());
+
+        for (text, error) in [
+            (
+                "OP_POP\nOP_RETURN",
+                "OP_POP at offset 0 pops more values than the stack holds",
+            ),
+            (
+                "OP_GET_GLOBAL 1\nOP_RETURN",
+                "OP_GET_GLOBAL at offset 0 needs a string constant",
+            ),
+            (
+                "OP_NIL\nOP_GET_LOCAL 1\nOP_RETURN",
+                "OP_GET_LOCAL at offset 1 reads slot 1 past the stack",
+            ),
+            // one path reaches the add with a single value
+            (
+                "OP_TRUE\nOP_JUMP_IF_FALSE 2\nOP_NIL\nOP_NIL\nOP_ADD\nOP_RETURN",
+                "OP_ADD at offset 6 pops more values than the stack holds",
+            ),
+        ] {
+            assert_eq!(Chunk::assemble(text).err().unwrap().to_string(), error);
+        }
+    }
+
+    #[test]
+    fn assemble_should_succeed() {
+        let chunk = Chunk::assemble(
+            r#"
+            # prints "a # b" and 3
+            OP_CONSTANT "a # b"
+            OP_CONSTANT 1
+            OP_CONSTANT 2   # trailing comment
+            add
+            OP_PRINT 2
+            OP_RETURN
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            chunk.code,
+            [
+                OpCode::OP_CONSTANT.into(),
+                0,
+                OpCode::OP_CONSTANT.into(),
+                1,
+                OpCode::OP_CONSTANT.into(),
+                2,
+                OpCode::OP_ADD.into(),
+                OpCode::OP_PRINT.into(),
+                2,
+                OpCode::OP_RETURN.into(),
+            ]
+        );
+        assert_eq!(chunk.lines, [3, 3, 4, 4, 5, 5, 6, 7, 7, 8]);
+        assert_eq!(chunk.constants[1], Value::VAL_NUMBER(1.));
+    }
+
+    #[test]
+    fn assemble_should_fail() {
+        for (text, error) in [
+            ("OP_NOPE", "line 1: unknown opcode 'OP_NOPE'"),
+            ("\nOP_ADD 1", "line 2: OP_ADD takes no operands"),
+            ("OP_PRINT x", "line 1: invalid byte operand 'x'"),
+            ("OP_CONSTANT one", "line 1: invalid constant 'one'"),
+            ("OP_CLOSURE 0", "line 1: OP_CLOSURE can't be assembled"),
+            (
+                "OP_JUMP 1\nOP_RETURN",
+                "jump at offset 0 doesn't land on an instruction",
+            ),
+        ] {
+            assert_eq!(Chunk::assemble(text).err().unwrap().to_string(), error);
+        }
     }
 }
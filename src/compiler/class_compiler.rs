@@ -0,0 +1,5 @@
+/// Tracks the class whose body is being compiled, so `this` and `super` can be rejected where they don't apply.
+pub struct ClassCompiler {
+    pub(crate) name: String,
+    pub(crate) has_superclass: bool,
+}
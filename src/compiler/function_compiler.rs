@@ -0,0 +1,91 @@
+use crate::chunk::Chunk;
+use crate::object::Function;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FunctionType {
+    TYPE_FUNCTION,
+    TYPE_INITIALIZER,
+    TYPE_METHOD,
+    TYPE_SCRIPT,
+}
+
+pub struct Local {
+    pub(crate) name: String,
+    /// `None` until the variable's initializer has been compiled
+    pub(crate) depth: Option<usize>,
+    /// set once a closure captures the local, so leaving its scope moves it off the stack
+    pub(crate) is_captured: bool,
+    /// declared with `const`, assigning to it is a compile error
+    pub(crate) is_const: bool,
+}
+
+/// Where a closure finds a captured variable when it is created.
+pub struct Upvalue {
+    /// slot of the local in the enclosing function, or index into the enclosing function's upvalues
+    pub(crate) index: u8,
+    pub(crate) is_local: bool,
+}
+
+/// A loop being compiled, the target of `break` and `continue`.
+pub struct Loop {
+    pub(crate) label: Option<String>,
+    /// where `continue` jumps back to, before the condition
+    pub(crate) start: usize,
+    /// scope depth outside the loop, locals deeper than this are popped when jumping out
+    pub(crate) scope_depth: usize,
+    /// `break` jumps to patch once the end of the loop is known
+    pub(crate) breaks: Vec<usize>,
+}
+
+/// State of a single function body being compiled, the compiler keeps one per nesting level.
+pub struct FunctionCompiler {
+    pub(crate) function: Function,
+    pub(crate) function_type: FunctionType,
+    pub(crate) locals: Vec<Local>,
+    pub(crate) upvalues: Vec<Upvalue>,
+    pub(crate) scope_depth: usize,
+    /// enclosing loops, innermost last
+    pub(crate) loops: Vec<Loop>,
+    /// offset of the last `OP_CALL` written, which is a tail call if a return follows it
+    pub(crate) last_call: Option<usize>,
+}
+
+impl FunctionCompiler {
+    pub fn new(function_type: FunctionType, name: String) -> Self {
+        // slot 0 holds the called function, or the receiver in methods. The script is run
+        // without a callee on the stack, so its locals start at slot 0.
+        let locals = match function_type {
+            FunctionType::TYPE_SCRIPT => vec![],
+            FunctionType::TYPE_FUNCTION => vec![Local {
+                name: String::new(),
+                depth: Some(0),
+                is_captured: false,
+                is_const: false,
+            }],
+            FunctionType::TYPE_INITIALIZER | FunctionType::TYPE_METHOD => vec![Local {
+                name: "this".to_string(),
+                depth: Some(0),
+                is_captured: false,
+                is_const: false,
+            }],
+        };
+
+        Self {
+            function: Function {
+                arity: 0,
+                defaults: vec![],
+                body: 0,
+                variadic: false,
+                upvalue_count: 0,
+                chunk: Chunk::default(),
+                name,
+            },
+            function_type,
+            locals,
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+            last_call: None,
+        }
+    }
+}
@@ -1,31 +1,60 @@
+use std::collections::HashSet;
 use std::iter::Scan;
+use std::rc::Rc;
 
 use num_enum::IntoPrimitive;
 
 use crate::chunk::Chunk;
+use crate::compiler::class_compiler::ClassCompiler;
+use crate::compiler::function_compiler::FunctionType::{
+    TYPE_FUNCTION, TYPE_INITIALIZER, TYPE_METHOD, TYPE_SCRIPT,
+};
+use crate::compiler::function_compiler::{FunctionCompiler, FunctionType, Local, Loop, Upvalue};
 use crate::compiler::parse_rule::{ParseFn, ParseRule};
 use crate::compiler::parser::Parser;
 use crate::compiler::precedence::Precedence;
 use crate::compiler::precedence::Precedence::PREC_NONE;
 use crate::compiler::scanner::Scanner;
 use crate::object::ObjectType::OBJ_STRING;
-use crate::object::{Object, ObjectType};
-use crate::op_code::OpCode::{
-    OP_ADD, OP_CONSTANT, OP_DIVIDE, OP_EQUAL, OP_FALSE, OP_GREATER, OP_LESS, OP_MULTIPLY,
-    OP_NEGATE, OP_NIL, OP_NOT, OP_RETURN, OP_SUBTRACT, OP_TRUE,
-};
+use crate::object::{Function, Object, ObjectType};
+use crate::op_code::{OpCode, OpCode::*};
 use crate::token::{Token, TokenType, TokenType::*};
 use crate::value::Value;
 
+mod class_compiler;
+mod function_compiler;
 mod parse_rule;
 mod parser;
 mod precedence;
 pub mod scanner;
 
+/// A compile error with the location of the token it was reported at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
 pub struct Compiler<'a> {
     parser: Parser,
     scanner: Scanner,
     compiling_chunk: &'a mut Chunk,
+    /// the function being compiled is last, the script is always first
+    functions: Vec<FunctionCompiler>,
+    classes: Vec<ClassCompiler>,
+    /// globals declared with `const` so far, assigning to them is a compile error
+    const_globals: HashSet<String>,
+    /// every error reported during the last `compile`, in source order
+    errors: Vec<CompileError>,
+    /// errors are printed to stderr as they are found unless collected by `collect_errors`
+    print_errors: bool,
+    /// ends the script with `OP_RETURN`, off for fragments spliced into another chunk
+    implicit_return: bool,
+    /// records the source span of every byte in the chunks, see `Chunk::span_of`
+    source_map: bool,
+    /// file the source came from, printed with errors when set
+    source_name: Option<String>,
 }
 
 impl<'a> Compiler<'a> {
@@ -34,53 +63,847 @@ impl<'a> Compiler<'a> {
             parser: Parser::new(),
             scanner: Scanner::new(vec![]),
             compiling_chunk: chunk,
+            functions: vec![],
+            classes: vec![],
+            const_globals: HashSet::new(),
+            errors: vec![],
+            print_errors: true,
+            implicit_return: true,
+            source_map: false,
+            source_name: None,
         }
     }
-    pub fn compile(&mut self, source: Vec<u8>) -> bool {
-        self.scanner.source = source;
 
-        self.parser.had_error = false;
-        self.parser.panic_mode = false;
+    /// Names the source in the errors printed from now on, like `[foo.lox:3:5] Error ...`.
+    pub fn set_source_name(&mut self, source_name: impl Into<String>) {
+        self.source_name = Some(source_name.into());
+    }
 
-        self.advance();
-        self.expression();
-        self.consume(TOKEN_EOF, "Expected end of expression.".to_string());
-        self.emit_byte(OP_RETURN.into());
+    /// Leaves the trailing `OP_RETURN` off the script, functions inside it still return.
+    pub fn with_implicit_return(mut self, implicit_return: bool) -> Self {
+        self.implicit_return = implicit_return;
+        self
+    }
 
-        if !self.parser.had_error {
-            self.compiling_chunk.disassemble_chunk("code".to_string());
+    /// Records which part of the source each byte was compiled from. Operators are attributed to
+    /// their own token, everything else to the token just before it was emitted.
+    pub fn with_source_map(mut self, source_map: bool) -> Self {
+        self.source_map = source_map;
+        self
+    }
+
+    pub fn compile(&mut self, source: Vec<u8>) -> bool {
+        self.scanner.reset(source);
+        self.parser = Parser::new();
+        *self.compiling_chunk = Chunk::default();
+        self.functions = vec![self.function_compiler(TYPE_SCRIPT, String::new())];
+        self.classes.clear();
+        self.const_globals.clear();
+        self.errors.clear();
+
+        self.advance();
+        while self.parser.current.is_some() && !self.match_token(TOKEN_EOF) {
+            self.declaration();
+            if self.parser.panic_mode {
+                self.synchronize();
+            }
         }
 
+        let script = self.end_compiler();
+        *self.compiling_chunk = script.function.chunk;
+
         !self.parser.had_error
     }
 
+    /// Compiles `source` into a chunk of its own, returning every error instead when it fails.
+    /// Nothing is printed.
+    pub fn compile_to_chunk(source: &str) -> Result<Chunk, Vec<CompileError>> {
+        let mut chunk = Chunk::default();
+        let errors = Compiler::new(&mut chunk).collect_errors(source.as_bytes().to_vec());
+        if errors.is_empty() {
+            Ok(chunk)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Compiles without printing anything, returning every error instead of stopping at the first.
+    pub fn collect_errors(&mut self, source: Vec<u8>) -> Vec<CompileError> {
+        self.print_errors = false;
+        self.compile(source);
+        self.print_errors = true;
+        self.errors.clone()
+    }
+
+    /// The first error of the last `compile`, if it failed.
+    pub fn first_error(&self) -> Option<(&str, usize)> {
+        self.errors
+            .first()
+            .map(|error| (error.message.as_str(), error.line))
+    }
+
+    fn function_compiler(&self, function_type: FunctionType, name: String) -> FunctionCompiler {
+        let mut compiler = FunctionCompiler::new(function_type, name);
+        if self.source_map {
+            compiler.function.chunk.record_spans();
+        }
+        compiler
+    }
+
+    fn current(&mut self) -> &mut FunctionCompiler {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn current_chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn end_compiler(&mut self) -> FunctionCompiler {
+        if self.implicit_return || self.current().function_type != TYPE_SCRIPT {
+            self.emit_return();
+        }
+        self.functions.pop().unwrap()
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.current().scope_depth -= 1;
+
+        let scope_depth = self.current().scope_depth;
+        let mut captured = vec![];
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= Some(scope_depth) {
+                break;
+            }
+
+            captured.push(local.is_captured);
+            self.current().locals.pop();
+        }
+        self.pop_locals(&captured);
+    }
+
+    /// Discards locals from the top of the stack down, given whether each one is captured.
+    /// Runs of uncaptured locals are popped by a single `OP_POPN`.
+    fn pop_locals(&mut self, captured: &[bool]) {
+        let mut pending: u8 = 0;
+        for &is_captured in captured {
+            if is_captured || pending == u8::MAX {
+                self.emit_pops(pending);
+                pending = 0;
+            }
+            if is_captured {
+                self.emit_byte(OP_CLOSE_UPVALUE.into());
+            } else {
+                pending += 1;
+            }
+        }
+        self.emit_pops(pending);
+    }
+
+    fn emit_pops(&mut self, count: u8) {
+        match count {
+            0 => {}
+            1 => self.emit_byte(OP_POP.into()),
+            _ => self.emit_bytes(OP_POPN.into(), count),
+        }
+    }
+
     fn advance(&mut self) {
-        self.parser.previous = self.parser.current.clone();
+        self.parser.previous = self.parser.current;
 
         loop {
             self.parser.current = self.scanner.scan_token();
 
-            if let Some(current) = self.parser.current.clone() {
+            if let Some(current) = self.parser.current {
                 if current.token_type != TOKEN_ERROR {
                     break;
                 }
 
-                self.error_at_current(current.message);
+                let message = self.lexeme(&current).to_string();
+                self.error_at_current(message);
             } else {
                 break;
             }
         }
     }
 
-    fn consume(&mut self, token_type: TokenType, error_message: String) {
-        if let Some(current_token) = &self.parser.current {
-            if current_token.token_type == token_type {
-                self.advance();
-                return;
-            }
+    fn consume(&mut self, token_type: TokenType, error_message: String) {
+        if let Some(current_token) = &self.parser.current {
+            if current_token.token_type == token_type {
+                self.advance();
+                return;
+            }
+        }
+
+        self.error_at_current(error_message);
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        matches!(&self.parser.current, Some(current) if current.token_type == token_type)
+    }
+
+    fn match_token(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+        self.advance();
+        true
+    }
+
+    /// Skips tokens until a statement boundary so one mistake doesn't cascade into more errors.
+    fn synchronize(&mut self) {
+        self.parser.panic_mode = false;
+
+        while self.parser.current.is_some() && !self.check(TOKEN_EOF) {
+            if matches!(&self.parser.previous, Some(previous) if previous.token_type == TOKEN_SEMICOLON)
+            {
+                return;
+            }
+            if matches!(
+                self.parser.current.as_ref().map(Token::token_type),
+                Some(
+                    TOKEN_CLASS
+                        | TOKEN_FUN
+                        | TOKEN_VAR
+                        | TOKEN_CONST
+                        | TOKEN_FOR
+                        | TOKEN_IF
+                        | TOKEN_WHILE
+                        | TOKEN_PRINT
+                        | TOKEN_ASSERT
+                        | TOKEN_RETURN
+                )
+            ) {
+                return;
+            }
+
+            self.advance();
+        }
+    }
+
+    fn declaration(&mut self) {
+        if self.match_token(TOKEN_CLASS) {
+            self.class_declaration();
+        } else if self.match_token(TOKEN_FUN) {
+            self.fun_declaration();
+        } else if self.match_token(TOKEN_VAR) {
+            self.var_declaration();
+        } else if self.match_token(TOKEN_CONST) {
+            self.const_declaration();
+        } else {
+            self.statement();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        self.consume(TOKEN_IDENTIFIER, "Expected class name.".to_string());
+        let class_name = self.parser.previous.unwrap();
+        let name_constant = self.identifier_constant(&class_name);
+        self.declare_variable();
+
+        self.emit_bytes(OP_CLASS.into(), name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassCompiler {
+            name: self.lexeme(&class_name).to_string(),
+            has_superclass: false,
+        });
+
+        if self.match_token(TOKEN_LESS) {
+            self.consume(TOKEN_IDENTIFIER, "Expected superclass name.".to_string());
+            let superclass_name = self.parser.previous.as_ref().unwrap();
+            if self.lexeme(superclass_name) == self.lexeme(&class_name) {
+                self.error("A class can't inherit from itself.".to_string());
+            }
+
+            self.variable(false);
+            self.named_variable(&class_name, false);
+            self.emit_byte(OP_INHERIT.into());
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        // keep the class on the stack while its methods are attached to it
+        self.named_variable(&class_name, false);
+        self.consume(
+            TOKEN_LEFT_BRACE,
+            "Expected '{' before class body.".to_string(),
+        );
+        while self.parser.current.is_some()
+            && !self.check(TOKEN_RIGHT_BRACE)
+            && !self.check(TOKEN_EOF)
+        {
+            self.method();
+        }
+        self.consume(
+            TOKEN_RIGHT_BRACE,
+            "Expected '}' after class body.".to_string(),
+        );
+        self.emit_byte(OP_POP.into());
+
+        self.classes.pop();
+    }
+
+    fn method(&mut self) {
+        self.consume(TOKEN_IDENTIFIER, "Expected method name.".to_string());
+        let name = self.parser.previous.unwrap();
+        let constant = self.identifier_constant(&name);
+
+        let function_type = if self.lexeme(&name) == "init" {
+            TYPE_INITIALIZER
+        } else {
+            TYPE_METHOD
+        };
+        let name = self.previous_lexeme();
+        self.function(function_type, name);
+        self.emit_bytes(OP_METHOD.into(), constant);
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expected function name.".to_string());
+        // a function may refer to itself, so it is usable before its body is compiled
+        self.mark_initialized();
+        let name = self.previous_lexeme();
+        self.function(TYPE_FUNCTION, name);
+        self.define_variable(global);
+    }
+
+    /// `fun (params) { body }` used as an expression, the function has no name.
+    fn lambda(&mut self) {
+        self.function(TYPE_FUNCTION, String::new());
+    }
+
+    fn function(&mut self, function_type: FunctionType, name: String) {
+        let compiler = self.function_compiler(function_type, name);
+        self.functions.push(compiler);
+        self.begin_scope();
+
+        self.consume(
+            TOKEN_LEFT_PAREN,
+            "Expected '(' after function name.".to_string(),
+        );
+        self.parameters();
+        self.consume(
+            TOKEN_LEFT_BRACE,
+            "Expected '{' before function body.".to_string(),
+        );
+        self.block();
+        self.end_function();
+    }
+
+    /// `(a, b) => expr`, an anonymous function returning `expr`. The `(` is already consumed.
+    fn arrow_function(&mut self) {
+        let compiler = self.function_compiler(TYPE_FUNCTION, String::new());
+        self.functions.push(compiler);
+        self.begin_scope();
+
+        self.parameters();
+        self.consume(TOKEN_ARROW, "Expected '=>' after parameters.".to_string());
+        self.expression();
+        self.emit_value_return();
+        self.end_function();
+    }
+
+    /// Declares the parameters of the innermost function, up to and including the `)`.
+    fn parameters(&mut self) {
+        if !self.check(TOKEN_RIGHT_PAREN) {
+            loop {
+                if self.current().function.arity == u8::MAX as usize {
+                    self.error_at_current("Can't have more than 255 parameters.".to_string());
+                }
+                self.current().function.arity += 1;
+
+                if self.match_token(TOKEN_ELLIPSIS) {
+                    self.rest_parameter();
+                    break;
+                }
+
+                let constant = self.parse_variable("Expected parameter name.".to_string());
+                // the initializer runs in the callee, only when the argument is missing
+                if self.match_token(TOKEN_EQUAL) {
+                    let start = self.current_chunk().count();
+                    self.current().function.defaults.push(start);
+                    self.expression();
+                } else if !self.current().function.defaults.is_empty() {
+                    self.error(
+                        "Parameters after one with a default need a default too.".to_string(),
+                    );
+                }
+                self.define_variable(constant);
+
+                if !self.match_token(TOKEN_COMMA) {
+                    break;
+                }
+            }
+        }
+        self.consume(
+            TOKEN_RIGHT_PAREN,
+            "Expected ')' after parameters.".to_string(),
+        );
+        self.current().function.body = self.current_chunk().count();
+    }
+
+    /// `...name`, the last parameter, which collects the remaining arguments into a list. The VM
+    /// builds the list when there are any, otherwise its default makes an empty one.
+    fn rest_parameter(&mut self) {
+        let constant = self.parse_variable("Expected parameter name after '...'.".to_string());
+        let start = self.current_chunk().count();
+        self.current().function.defaults.push(start);
+        self.emit_bytes(OP_BUILD_LIST.into(), 0);
+        self.define_variable(constant);
+        self.current().function.variadic = true;
+
+        if self.check(TOKEN_COMMA) {
+            self.error_at_current("The rest parameter must be the last one.".to_string());
+        }
+    }
+
+    /// Finishes the innermost function and emits the closure that creates it.
+    fn end_function(&mut self) {
+        // the function's locals are discarded with its frame, no need to end the scope
+        let compiler = self.end_compiler();
+        let function = Object {
+            object_type: ObjectType::OBJ_FUNCTION(Rc::new(compiler.function)),
+        };
+        let constant = self.make_constant(Value::VAL_OBJECT(function));
+        self.emit_bytes(OP_CLOSURE.into(), constant);
+
+        for upvalue in compiler.upvalues {
+            self.emit_bytes(upvalue.is_local.into(), upvalue.index);
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expected variable name.".to_string());
+
+        if self.match_token(TOKEN_EQUAL) {
+            self.expression();
+        } else {
+            self.emit_byte(OP_NIL.into());
+        }
+        self.consume(
+            TOKEN_SEMICOLON,
+            "Expected ';' after variable declaration.".to_string(),
+        );
+
+        self.define_variable(global);
+    }
+
+    fn const_declaration(&mut self) {
+        let global = self.parse_variable("Expected constant name.".to_string());
+        if self.current().scope_depth > 0 {
+            if let Some(local) = self.current().locals.last_mut() {
+                local.is_const = true;
+            }
+        } else {
+            let name = self.previous_lexeme();
+            self.const_globals.insert(name);
+        }
+
+        self.consume(TOKEN_EQUAL, "Expected '=' after constant name.".to_string());
+        self.expression();
+        self.consume(
+            TOKEN_SEMICOLON,
+            "Expected ';' after constant declaration.".to_string(),
+        );
+
+        if self.current().scope_depth > 0 {
+            self.mark_initialized();
+        } else {
+            self.emit_bytes(OP_DEFINE_CONST_GLOBAL.into(), global);
+        }
+    }
+
+    /// Returns the constant holding the variable name for globals, or 0 for locals.
+    fn parse_variable(&mut self, error_message: String) -> u8 {
+        self.consume(TOKEN_IDENTIFIER, error_message);
+
+        self.declare_variable();
+        if self.current().scope_depth > 0 {
+            return 0;
+        }
+
+        let name = self.parser.previous.unwrap();
+        self.identifier_constant(&name)
+    }
+
+    fn declare_variable(&mut self) {
+        if self.current().scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous_lexeme();
+        let scope_depth = self.current().scope_depth;
+        let shadows = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= scope_depth))
+            .any(|local| local.name == name);
+        if shadows {
+            self.error("Already a variable with this name in this scope.".to_string());
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: String) {
+        if self.current().locals.len() == u8::MAX as usize + 1 {
+            self.error("Too many local variables in function.".to_string());
+            return;
+        }
+
+        self.current().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+            is_const: false,
+        });
+    }
+
+    /// Looks `name` up among the locals of `self.functions[function]`.
+    fn resolve_local(&mut self, function: usize, name: &str) -> Option<u8> {
+        let (slot, local) = self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)?;
+
+        if local.depth.is_none() {
+            self.error("Can't read local variable in its own initializer.".to_string());
+        }
+        Some(slot as u8)
+    }
+
+    /// Looks `name` up in the functions enclosing `self.functions[function]`, capturing it
+    /// into every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, index, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let upvalues = &self.functions[function].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing as u8;
+        }
+
+        if upvalues.len() == u8::MAX as usize + 1 {
+            self.error("Too many closure variables in function.".to_string());
+            return 0;
+        }
+
+        let compiler = &mut self.functions[function];
+        compiler.upvalues.push(Upvalue { index, is_local });
+        compiler.function.upvalue_count = compiler.upvalues.len();
+        (compiler.upvalues.len() - 1) as u8
+    }
+
+    fn mark_initialized(&mut self) {
+        let scope_depth = self.current().scope_depth;
+        if scope_depth == 0 {
+            return;
+        }
+
+        if let Some(local) = self.current().locals.last_mut() {
+            local.depth = Some(scope_depth);
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> u8 {
+        let name = self.clone_string(self.lexeme(name).to_string());
+        self.make_constant(Value::VAL_OBJECT(name))
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.current().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_bytes(OP_DEFINE_GLOBAL.into(), global);
+    }
+
+    fn statement(&mut self) {
+        let labeled = self.check(TOKEN_IDENTIFIER)
+            && matches!(self.scanner.peek_token(), Some(token) if token.token_type == TOKEN_COLON);
+        if labeled {
+            self.advance();
+            let label = self.previous_lexeme();
+            self.advance();
+            if self.match_token(TOKEN_FOR) {
+                self.for_statement(Some(label));
+            } else {
+                self.consume(TOKEN_WHILE, "Expected a loop after label.".to_string());
+                self.while_statement(Some(label));
+            }
+        } else if self.match_token(TOKEN_PRINT) {
+            self.print_statement();
+        } else if self.match_token(TOKEN_ASSERT) {
+            self.assert_statement();
+        } else if self.match_token(TOKEN_RETURN) {
+            self.return_statement();
+        } else if self.match_token(TOKEN_WHILE) {
+            self.while_statement(None);
+        } else if self.match_token(TOKEN_FOR) {
+            self.for_statement(None);
+        } else if self.match_token(TOKEN_BREAK) {
+            self.break_statement();
+        } else if self.match_token(TOKEN_CONTINUE) {
+            self.continue_statement();
+        } else if self.match_token(TOKEN_LEFT_BRACE) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    /// `print a, b, c;` prints every value separated by a space, followed by a newline.
+    fn print_statement(&mut self) {
+        let mut arg_count: u8 = 0;
+        loop {
+            self.expression();
+            if arg_count == u8::MAX {
+                self.error("Can't print more than 255 values.".to_string());
+            }
+            arg_count = arg_count.saturating_add(1);
+
+            if !self.match_token(TOKEN_COMMA) {
+                break;
+            }
+        }
+        self.consume(TOKEN_SEMICOLON, "Expected ';' after value.".to_string());
+        self.emit_bytes(OP_PRINT.into(), arg_count);
+    }
+
+    fn assert_statement(&mut self) {
+        self.expression();
+        if self.match_token(TOKEN_COMMA) {
+            self.expression();
+        } else {
+            let message = self.clone_string("Assertion failed.".to_string());
+            self.emit_constant(Value::VAL_OBJECT(message));
+        }
+        self.consume(TOKEN_SEMICOLON, "Expected ';' after assertion.".to_string());
+        self.emit_byte(OP_ASSERT.into());
+    }
+
+    fn return_statement(&mut self) {
+        let function_type = self.current().function_type;
+        if function_type == TYPE_SCRIPT {
+            self.error("Can't return from top-level code.".to_string());
+        }
+
+        if self.match_token(TOKEN_SEMICOLON) {
+            self.emit_return();
+        } else {
+            if function_type == TYPE_INITIALIZER {
+                self.error("Can't return a value from an initializer.".to_string());
+            }
+
+            self.expression();
+            self.consume(
+                TOKEN_SEMICOLON,
+                "Expected ';' after return value.".to_string(),
+            );
+            self.emit_value_return();
+        }
+    }
+
+    /// Returns the value on top of the stack. When it comes straight from a call or a method
+    /// invocation, that becomes a tail call that reuses the returning function's frame.
+    /// `super.method()` calls aren't, they keep their frame.
+    fn emit_value_return(&mut self) {
+        let end = self.current_chunk().code.len();
+        if let Some(call) = self.current().last_call {
+            let code = &mut self.current_chunk().code;
+            let tail = match code.get(call).map(|&op_code| OpCode::try_from(op_code)) {
+                Some(Ok(OP_CALL)) if call + 2 == end => Some(OP_TAIL_CALL),
+                Some(Ok(OP_INVOKE)) if call + 3 == end => Some(OP_TAIL_INVOKE),
+                _ => None,
+            };
+            if let Some(tail) = tail {
+                code[call] = tail.into();
+            }
+        }
+        self.emit_byte(OP_RETURN.into());
+    }
+
+    fn while_statement(&mut self, label: Option<String>) {
+        let loop_start = self.current_chunk().code.len();
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop {
+            label,
+            start: loop_start,
+            scope_depth,
+            breaks: vec![],
+        });
+
+        self.consume(TOKEN_LEFT_PAREN, "Expected '(' after 'while'.".to_string());
+        self.expression();
+        self.consume(
+            TOKEN_RIGHT_PAREN,
+            "Expected ')' after condition.".to_string(),
+        );
+
+        let exit_jump = self.emit_jump(OP_JUMP_IF_FALSE);
+        self.emit_byte(OP_POP.into());
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OP_POP.into());
+
+        // breaks land after the condition is popped, they left the loop without one on the stack
+        let finished = self.current().loops.pop().unwrap();
+        for jump in finished.breaks {
+            self.patch_jump(jump);
+        }
+    }
+
+    /// `for x in items body` runs the body once per item, each time with a new `x`. Ranges and
+    /// lists are iterated directly, an instance by calling its `next()` until that returns `nil`.
+    fn for_statement(&mut self, label: Option<String>) {
+        self.consume(
+            TOKEN_IDENTIFIER,
+            "Expected loop variable name after 'for'.".to_string(),
+        );
+        let name = self.previous_lexeme();
+        self.consume(TOKEN_IN, "Expected 'in' after loop variable.".to_string());
+
+        // what's iterated and how far into it the loop is live in unnamed locals around the loop
+        self.begin_scope();
+        self.expression();
+        self.add_local(String::new());
+        self.mark_initialized();
+        self.emit_constant(Value::VAL_INDEX(0));
+        self.add_local(String::new());
+        self.mark_initialized();
+
+        let loop_start = self.current_chunk().code.len();
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop {
+            label,
+            start: loop_start,
+            scope_depth,
+            breaks: vec![],
+        });
+
+        let exit_jump = self.emit_jump(OP_ITER_NEXT);
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        let finished = self.current().loops.pop().unwrap();
+        for jump in finished.breaks {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+    }
+
+    /// `break;` or `break label;` jumps past the end of the innermost or the labeled loop.
+    fn break_statement(&mut self) {
+        let Some(index) = self.target_loop("break") else {
+            return;
+        };
+
+        self.pop_loop_locals(index);
+        let jump = self.emit_jump(OP_JUMP);
+        self.current().loops[index].breaks.push(jump);
+    }
+
+    /// `continue;` or `continue label;` jumps back to the condition of the innermost or the labeled loop.
+    fn continue_statement(&mut self) {
+        let Some(index) = self.target_loop("continue") else {
+            return;
+        };
+
+        self.pop_loop_locals(index);
+        let start = self.current().loops[index].start;
+        self.emit_loop(start);
+    }
+
+    /// Parses the optional label and `;` after `break` or `continue`, returning the loop it refers to.
+    fn target_loop(&mut self, keyword: &str) -> Option<usize> {
+        let label = if self.match_token(TOKEN_IDENTIFIER) {
+            Some(self.previous_lexeme())
+        } else {
+            None
+        };
+        self.consume(TOKEN_SEMICOLON, format!("Expected ';' after '{keyword}'."));
+
+        let loops = &self.current().loops;
+        let index = match &label {
+            Some(label) => loops
+                .iter()
+                .rposition(|target| target.label.as_ref() == Some(label)),
+            None => loops.len().checked_sub(1),
+        };
+        if index.is_none() {
+            match label {
+                Some(label) => self.error(format!("No enclosing loop labeled '{label}'.")),
+                None => self.error(format!("Can't use '{keyword}' outside of a loop.")),
+            }
+        }
+        index
+    }
+
+    /// Discards the locals declared inside `self.current().loops[index]` without ending their scopes.
+    fn pop_loop_locals(&mut self, index: usize) {
+        let scope_depth = self.current().loops[index].scope_depth;
+        let captured = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth > scope_depth))
+            .map(|local| local.is_captured)
+            .collect::<Vec<_>>();
+
+        self.pop_locals(&captured);
+    }
+
+    fn block(&mut self) {
+        while self.parser.current.is_some()
+            && !self.check(TOKEN_RIGHT_BRACE)
+            && !self.check(TOKEN_EOF)
+        {
+            self.declaration();
         }
+        self.consume(TOKEN_RIGHT_BRACE, "Expected '}' after block.".to_string());
+    }
 
-        self.error_at_current(error_message);
+    fn expression_statement(&mut self) {
+        self.expression();
+        // a trailing expression without ';' is the result of the script and stays on the stack
+        if self.check(TOKEN_EOF) {
+            return;
+        }
+        self.consume(
+            TOKEN_SEMICOLON,
+            "Expected ';' after expression.".to_string(),
+        );
+        self.emit_byte(OP_POP.into());
     }
 
     fn expression(&mut self) {
@@ -90,8 +913,12 @@ impl<'a> Compiler<'a> {
     fn parse_precedence(&mut self, precedence: Precedence) {
         self.advance();
 
-        if let Some(previous) = &self.parser.previous.clone() {
-            let rule = self.get_rule(&previous.clone().token_type);
+        // where the left operand of any infix operator below starts
+        let start = self.current_chunk().count();
+
+        let can_assign = precedence <= Precedence::PREC_ASSIGNMENT;
+        if let Some(previous) = self.parser.previous {
+            let rule = self.get_rule(&previous.token_type);
             let prefix_rule = rule.prefix;
 
             match prefix_rule {
@@ -100,6 +927,13 @@ impl<'a> Compiler<'a> {
                 ParseFn::Number => self.number(),
                 ParseFn::Literal => self.literal(),
                 ParseFn::String => self.string(),
+                ParseFn::Interpolation => self.interpolation(),
+                ParseFn::Lambda => self.lambda(),
+                ParseFn::Variable => self.variable(can_assign),
+                ParseFn::List => self.list(),
+                ParseFn::Map => self.map(),
+                ParseFn::Super => self.super_(),
+                ParseFn::This => self.this(),
                 ParseFn::Null => {
                     self.error("Expected expression.".to_string());
                     return;
@@ -108,23 +942,35 @@ impl<'a> Compiler<'a> {
             }
         }
 
-        while let Some(current) = self.parser.current.clone() {
+        while let Some(current) = self.parser.current {
             if precedence > self.get_rule(&current.token_type).precedence {
                 break;
             }
             self.advance();
-            if let Some(previous) = &self.parser.previous.clone() {
-                let infix_rule = self.get_rule(&previous.clone().token_type).infix;
+            if let Some(previous) = self.parser.previous {
+                let infix_rule = self.get_rule(&previous.token_type).infix;
                 match infix_rule {
-                    ParseFn::Binary => self.binary(),
+                    ParseFn::Binary => self.binary(start),
+                    ParseFn::Call => self.call(),
+                    ParseFn::Dot => self.dot(can_assign),
+                    ParseFn::Index => self.index(can_assign),
                     ParseFn::Null => {}
                     _ => unreachable!(),
                 }
             }
         }
+
+        if can_assign && self.match_token(TOKEN_EQUAL) {
+            self.error("Invalid assignment target.".to_string());
+        }
     }
 
     fn grouping(&mut self) {
+        if self.is_arrow_function() {
+            self.arrow_function();
+            return;
+        }
+
         self.expression();
         self.consume(
             TOKEN_RIGHT_PAREN,
@@ -132,46 +978,150 @@ impl<'a> Compiler<'a> {
         );
     }
 
+    /// Looks past the `(` just consumed for `a, b) =>` without consuming any tokens.
+    fn is_arrow_function(&mut self) -> bool {
+        let checkpoint = self.scanner.checkpoint();
+        let mut token = self.parser.current;
+        let mut expect_name = true;
+        let is_arrow = loop {
+            match token.map(|token| token.token_type) {
+                Some(TOKEN_RIGHT_PAREN) => {
+                    break self
+                        .scanner
+                        .scan_token()
+                        .is_some_and(|token| token.token_type == TOKEN_ARROW)
+                }
+                Some(TOKEN_ELLIPSIS) if expect_name => {}
+                Some(TOKEN_IDENTIFIER) if expect_name => expect_name = false,
+                Some(TOKEN_COMMA) if !expect_name => expect_name = true,
+                _ => break false,
+            }
+            token = self.scanner.scan_token();
+        };
+        self.scanner.restore(checkpoint);
+        is_arrow
+    }
+
     fn unary(&mut self) {
-        if let Some(previous) = &self.parser.previous.clone() {
-            let operator_type = &previous.token_type.clone();
+        if let Some(previous) = self.parser.previous {
+            let operator_type = previous.token_type;
 
+            let operand_start = self.current_chunk().count();
             self.parse_precedence(Precedence::PREC_UNARY);
 
-            match *operator_type {
-                TOKEN_MINUS => self.emit_byte(OP_NEGATE.into()),
-                TOKEN_BANG => self.emit_byte(OP_NOT.into()),
-                _ => unreachable!(),
+            if operator_type == TOKEN_MINUS {
+                if let Some(operand) = self.number_constant(operand_start) {
+                    self.replace_with_constant(operand_start, -operand);
+                    return;
+                }
             }
+
+            let op_code = match operator_type {
+                TOKEN_MINUS => OP_NEGATE,
+                TOKEN_BANG => OP_NOT,
+                _ => unreachable!(),
+            };
+            self.emit_byte_for(op_code.into(), &previous);
         }
     }
 
-    fn binary(&mut self) {
-        if let Some(previous) = &self.parser.previous.clone() {
-            let operator_type = &previous.token_type;
-            let parse_rule = self.get_rule(&operator_type.clone());
+    fn binary(&mut self, left_start: usize) {
+        if let Some(previous) = self.parser.previous {
+            let operator_type = previous.token_type;
+            let parse_rule = self.get_rule(&operator_type);
             let precedence: u8 = parse_rule.precedence.into();
 
+            let right_start = self.current_chunk().count();
             self.parse_precedence(Precedence::try_from(precedence + 1).unwrap());
 
-            match operator_type {
-                TOKEN_PLUS => self.emit_byte(OP_ADD.into()),
-                TOKEN_MINUS => self.emit_byte(OP_SUBTRACT.into()),
-                TOKEN_STAR => self.emit_byte(OP_MULTIPLY.into()),
-                TOKEN_SLASH => self.emit_byte(OP_DIVIDE.into()),
-                TOKEN_BANG_EQUAL => self.emit_bytes(OP_EQUAL.into(), OP_NOT.into()),
-                TOKEN_EQUAL_EQUAL => self.emit_byte(OP_EQUAL.into()),
-                TOKEN_GREATER => self.emit_byte(OP_GREATER.into()),
-                TOKEN_GREATER_EQUAL => self.emit_bytes(OP_LESS.into(), OP_NOT.into()),
-                TOKEN_LESS => self.emit_byte(OP_LESS.into()),
-                TOKEN_LESS_EQUAL => self.emit_bytes(OP_GREATER.into(), OP_NOT.into()),
+            if self.fold_binary(operator_type, left_start, right_start) {
+                return;
+            }
+
+            let op_codes: &[OpCode] = match operator_type {
+                TOKEN_PLUS => &[OP_ADD],
+                TOKEN_MINUS => &[OP_SUBTRACT],
+                TOKEN_STAR => &[OP_MULTIPLY],
+                TOKEN_SLASH => &[OP_DIVIDE],
+                TOKEN_BANG_EQUAL => &[OP_EQUAL, OP_NOT],
+                TOKEN_EQUAL_EQUAL => &[OP_EQUAL],
+                TOKEN_GREATER => &[OP_GREATER],
+                TOKEN_GREATER_EQUAL => &[OP_LESS, OP_NOT],
+                TOKEN_LESS => &[OP_LESS],
+                TOKEN_LESS_EQUAL => &[OP_GREATER, OP_NOT],
+                TOKEN_IS => &[OP_IS],
+                TOKEN_DOT_DOT => &[OP_RANGE],
                 _ => unreachable!(),
+            };
+            // attributed to the operator rather than the end of the right operand
+            for &op_code in op_codes {
+                self.emit_byte_for(op_code.into(), &previous);
             }
         }
     }
 
+    /// Replaces arithmetic on two literal numbers with its result, so `2 + 3` compiles to a
+    /// single `OP_CONSTANT 5`. Division by zero is left for the VM.
+    fn fold_binary(
+        &mut self,
+        operator_type: TokenType,
+        left_start: usize,
+        right_start: usize,
+    ) -> bool {
+        let (Some(a), Some(b)) = (
+            self.number_constant_between(left_start, right_start),
+            self.number_constant(right_start),
+        ) else {
+            return false;
+        };
+
+        let result = match operator_type {
+            TOKEN_PLUS => a + b,
+            TOKEN_MINUS => a - b,
+            TOKEN_STAR => a * b,
+            TOKEN_SLASH if b != 0.0 => a / b,
+            _ => return false,
+        };
+        self.replace_with_constant(left_start, result);
+        true
+    }
+
+    /// The number loaded by the code from `start` to the end, if that is a single `OP_CONSTANT`.
+    fn number_constant(&mut self, start: usize) -> Option<f32> {
+        let end = self.current_chunk().count();
+        self.number_constant_between(start, end)
+    }
+
+    fn number_constant_between(&mut self, start: usize, end: usize) -> Option<f32> {
+        let chunk = self.current_chunk();
+        let &[op_code, constant] = &chunk.code[start..end] else {
+            return None;
+        };
+        if op_code != OP_CONSTANT.into() {
+            return None;
+        }
+
+        match chunk.constants[constant as usize] {
+            Value::VAL_NUMBER(number) => Some(number),
+            _ => None,
+        }
+    }
+
+    /// Drops the folded code from `start` on, and the constants only it loaded, then loads `number`.
+    fn replace_with_constant(&mut self, start: usize, number: f32) {
+        let chunk = self.current_chunk();
+        // the folded code is nothing but `OP_CONSTANT`s
+        let loaded: Vec<usize> = chunk.code[start..]
+            .chunks(2)
+            .map(|instruction| instruction[1] as usize)
+            .collect();
+        chunk.truncate(start);
+        chunk.drop_constants(&loaded, start);
+        self.emit_constant(Value::VAL_NUMBER(number));
+    }
+
     fn literal(&mut self) {
-        if let Some(previous) = self.parser.previous.clone() {
+        if let Some(previous) = self.parser.previous {
             match previous.token_type {
                 TOKEN_FALSE => self.emit_byte(OP_FALSE.into()),
                 TOKEN_TRUE => self.emit_byte(OP_TRUE.into()),
@@ -182,8 +1132,232 @@ impl<'a> Compiler<'a> {
     }
 
     fn string(&mut self) {
-        if let Some(previous) = self.parser.previous.clone() {
-            self.emit_constant(Value::VAL_OBJECT(self.clone_string(previous.message)))
+        if let Some(previous) = self.parser.previous {
+            // trim the surrounding quotes, an interpolated string's last part starts with `}`
+            let lexeme = self.lexeme(&previous);
+            let quotes = if lexeme.len() >= 6 && lexeme.starts_with("\"\"\"") {
+                3
+            } else {
+                1
+            };
+            self.string_part(quotes, quotes);
+        }
+    }
+
+    /// `"a ${b} c"` arrives as the interpolation `"a ${`, the tokens of `b`, then the string `} c"`.
+    /// Each expression is converted to a string and the parts are joined with `OP_ADD`.
+    fn interpolation(&mut self) {
+        // each interpolation runs from `"` or `}` up to `${`
+        self.string_part(1, 2);
+        loop {
+            self.expression();
+            self.emit_bytes(OP_TO_STRING.into(), OP_ADD.into());
+            if !self.match_token(TOKEN_INTERPOLATION) {
+                break;
+            }
+            self.string_part(1, 2);
+            self.emit_byte(OP_ADD.into());
+        }
+
+        if !self.check(TOKEN_STRING) {
+            self.error_at_current("Expected '}' after interpolated expression.".to_string());
+            return;
+        }
+        self.advance();
+        self.string_part(1, 1);
+        self.emit_byte(OP_ADD.into());
+    }
+
+    /// Emits the previous token's text as a string constant, less `start` and `end` bytes of
+    /// delimiters, after decoding its escapes.
+    fn string_part(&mut self, start: usize, end: usize) {
+        let lexeme = self.lexeme(&self.parser.previous.unwrap());
+        match unescape(&lexeme[start..lexeme.len() - end]) {
+            Ok(string) => self.emit_constant(Value::VAL_OBJECT(self.clone_string(string))),
+            Err(message) => self.error(message),
+        }
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        if let Some(previous) = self.parser.previous {
+            self.named_variable(&previous, can_assign);
+        }
+    }
+
+    fn named_variable(&mut self, name: &Token, can_assign: bool) {
+        let function = self.functions.len() - 1;
+        let lexeme = self.lexeme(name).to_string();
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(function, &lexeme) {
+            (OP_GET_LOCAL, OP_SET_LOCAL, slot)
+        } else if let Some(index) = self.resolve_upvalue(function, &lexeme) {
+            (OP_GET_UPVALUE, OP_SET_UPVALUE, index)
+        } else {
+            (OP_GET_GLOBAL, OP_SET_GLOBAL, self.identifier_constant(name))
+        };
+
+        if can_assign && self.match_token(TOKEN_EQUAL) {
+            if self.is_const(&lexeme) {
+                self.error(format!("Can't assign to constant '{lexeme}'."));
+            }
+            self.expression();
+            self.emit_bytes(set_op.into(), arg);
+        } else {
+            self.emit_bytes(get_op.into(), arg);
+        }
+    }
+
+    /// Whether `name` resolves to a `const`, searching the scopes in the order `named_variable`
+    /// does. Globals declared in an earlier compile aren't known here, the VM rejects those.
+    fn is_const(&self, name: &str) -> bool {
+        for function in self.functions.iter().rev() {
+            if let Some(local) = function
+                .locals
+                .iter()
+                .rev()
+                .find(|local| local.name == name)
+            {
+                return local.is_const;
+            }
+        }
+        self.const_globals.contains(name)
+    }
+
+    fn super_(&mut self) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class.".to_string()),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass.".to_string())
+            }
+            Some(_) => {}
+        }
+
+        self.consume(TOKEN_DOT, "Expected '.' after 'super'.".to_string());
+        self.consume(
+            TOKEN_IDENTIFIER,
+            "Expected superclass method name.".to_string(),
+        );
+        let name = self.parser.previous.unwrap();
+        let name = self.identifier_constant(&name);
+
+        let this = Token::synthetic(TOKEN_THIS, "this");
+        self.named_variable(&this, false);
+        if self.match_token(TOKEN_LEFT_PAREN) {
+            let arg_count = self.argument_list();
+            self.emit_bytes(OP_SUPER_INVOKE.into(), name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_bytes(OP_GET_SUPER.into(), name);
+        }
+    }
+
+    fn this(&mut self) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.".to_string());
+            return;
+        }
+
+        self.variable(false);
+    }
+
+    fn list(&mut self) {
+        let item_count = self.comma_separated(
+            TOKEN_RIGHT_BRACKET,
+            "Can't have more than 255 items in a list literal.",
+            Self::expression,
+        );
+        self.consume(
+            TOKEN_RIGHT_BRACKET,
+            "Expected ']' after list items.".to_string(),
+        );
+        self.emit_bytes(OP_BUILD_LIST.into(), item_count);
+    }
+
+    /// Compiles `item`s separated by commas up to `close`, which is left for the caller to consume.
+    /// A trailing comma before `close` is allowed. Returns how many items there were.
+    fn comma_separated(&mut self, close: TokenType, too_many: &str, item: fn(&mut Self)) -> u8 {
+        let mut count: u8 = 0;
+        while !self.check(close) {
+            item(self);
+            if count == u8::MAX {
+                self.error(too_many.to_string());
+            }
+            count = count.saturating_add(1);
+
+            if !self.match_token(TOKEN_COMMA) {
+                break;
+            }
+        }
+        count
+    }
+
+    fn call(&mut self) {
+        let arg_count = self.argument_list();
+        self.current().last_call = Some(self.current_chunk().code.len());
+        self.emit_bytes(OP_CALL.into(), arg_count);
+    }
+
+    fn argument_list(&mut self) -> u8 {
+        let arg_count = self.comma_separated(
+            TOKEN_RIGHT_PAREN,
+            "Can't have more than 255 arguments.",
+            Self::expression,
+        );
+        self.consume(
+            TOKEN_RIGHT_PAREN,
+            "Expected ')' after arguments.".to_string(),
+        );
+        arg_count
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        self.consume(
+            TOKEN_IDENTIFIER,
+            "Expected property name after '.'.".to_string(),
+        );
+        let name = self.parser.previous.unwrap();
+        let name = self.identifier_constant(&name);
+
+        if can_assign && self.match_token(TOKEN_EQUAL) {
+            self.expression();
+            self.emit_bytes(OP_SET_PROPERTY.into(), name);
+        } else if self.match_token(TOKEN_LEFT_PAREN) {
+            let arg_count = self.argument_list();
+            self.current().last_call = Some(self.current_chunk().code.len());
+            self.emit_bytes(OP_INVOKE.into(), name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_bytes(OP_GET_PROPERTY.into(), name);
+        }
+    }
+
+    fn map(&mut self) {
+        let entry_count = self.comma_separated(
+            TOKEN_RIGHT_BRACE,
+            "Can't have more than 255 entries in a map literal.",
+            Self::map_entry,
+        );
+        self.consume(
+            TOKEN_RIGHT_BRACE,
+            "Expected '}' after map entries.".to_string(),
+        );
+        self.emit_bytes(OP_BUILD_MAP.into(), entry_count);
+    }
+
+    fn map_entry(&mut self) {
+        self.expression();
+        self.consume(TOKEN_COLON, "Expected ':' after map key.".to_string());
+        self.expression();
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TOKEN_RIGHT_BRACKET, "Expected ']' after index.".to_string());
+
+        if can_assign && self.match_token(TOKEN_EQUAL) {
+            self.expression();
+            self.emit_byte(OP_INDEX_SET.into());
+        } else {
+            self.emit_byte(OP_INDEX_GET.into());
         }
     }
 
@@ -192,41 +1366,90 @@ impl<'a> Compiler<'a> {
     }
 
     fn emit_byte(&mut self, byte: u8) {
-        if let Some(previous) = &self.parser.previous {
-            self.compiling_chunk.write(byte, previous.line);
+        if let Some(previous) = self.parser.previous {
+            self.emit_byte_for(byte, &previous);
         }
     }
 
+    /// Writes `byte` as compiled from `token`.
+    fn emit_byte_for(&mut self, byte: u8, token: &Token) {
+        let span = token.span();
+        self.current_chunk().write_with_span(byte, token.line, span);
+    }
+
     fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
         self.emit_byte(byte1);
         self.emit_byte(byte2);
     }
 
+    fn emit_jump(&mut self, op_code: OpCode) -> usize {
+        let line = self.parser.previous.map_or(0, |previous| previous.line);
+        self.current_chunk().emit_jump(op_code, line)
+    }
+
+    /// Initializers always hand back `this`, other functions return `nil` unless told otherwise.
+    /// The script leaves its trailing expression, if any, on the stack instead.
+    fn emit_return(&mut self) {
+        match self.current().function_type {
+            TYPE_SCRIPT => {}
+            TYPE_INITIALIZER => self.emit_bytes(OP_GET_LOCAL.into(), 0),
+            TYPE_FUNCTION | TYPE_METHOD => self.emit_byte(OP_NIL.into()),
+        }
+        self.emit_byte(OP_RETURN.into());
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        if let Err(e) = self.current_chunk().patch_jump(offset) {
+            self.error(e.to_string());
+        }
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OP_LOOP.into());
+
+        let offset = self.current_chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.".to_string());
+        }
+
+        self.emit_bytes(((offset >> 8) & 0xff) as u8, (offset & 0xff) as u8);
+    }
+
     fn emit_constant(&mut self, value: Value) {
         let constant = self.make_constant(value);
         self.emit_bytes(OP_CONSTANT.into(), constant);
     }
 
     fn make_constant(&mut self, value: Value) -> u8 {
-        self.compiling_chunk.add_constant(value) as u8
+        self.current_chunk().add_constant(value) as u8
     }
 
     fn number(&mut self) {
         if let Some(token) = &self.parser.previous {
-            match token.message.clone().parse::<Value>() {
+            let lexeme = self.lexeme(token);
+            match lexeme.parse::<Value>() {
                 Ok(value) => self.emit_constant(value),
-                Err(e) => panic!("constant {} is not valid {}", &token.message, e),
+                Err(e) => panic!("constant {lexeme} is not valid {e}"),
             }
         }
     }
 
+    fn lexeme(&self, token: &Token) -> &str {
+        token.lexeme(self.scanner.source())
+    }
+
+    fn previous_lexeme(&self) -> String {
+        self.lexeme(self.parser.previous.as_ref().unwrap())
+            .to_string()
+    }
+
     fn error(&mut self, message: String) {
-        self.error_at(&self.parser.previous.clone().unwrap(), message);
+        self.error_at(&self.parser.previous.unwrap(), message);
     }
 
     fn error_at_current(&mut self, message: String) {
-        if let Some(current) = &self.parser.current.clone() {
-            self.error_at(current, message)
+        if let Some(current) = self.parser.current {
+            self.error_at(&current, message)
         }
     }
 
@@ -235,37 +1458,168 @@ impl<'a> Compiler<'a> {
             return;
         }
         self.parser.panic_mode = true;
-        eprint!("[{}:{}] Error", token.line, token.start);
-        if token.token_type == TOKEN_EOF {
-            eprint!(" at end");
-        } else if token.token_type == TOKEN_ERROR {
-        } else {
-            eprint!(" at '{:?}'", token.token_type);
-        }
+        if self.print_errors {
+            match &self.source_name {
+                Some(name) => eprint!("[{name}:{}:{}] Error", token.line, token.column),
+                None => eprint!("[{}:{}] Error", token.line, token.start),
+            }
+            if token.token_type == TOKEN_EOF {
+                eprint!(" at end");
+            } else if token.token_type == TOKEN_ERROR {
+            } else {
+                eprint!(" at '{:?}'", token.token_type);
+            }
 
-        println!(": {message}");
+            eprintln!(": {message}");
+        }
         self.parser.had_error = true;
+        self.errors.push(CompileError {
+            message,
+            line: token.line,
+            column: token.column,
+        });
     }
 
     fn clone_string(&self, string: String) -> Object {
         Object {
-            object_type: OBJ_STRING(string),
+            object_type: OBJ_STRING(string.into()),
+        }
+    }
+}
+
+/// Decodes the escapes in a string literal's contents: `\n`, `\t`, `\r`, `\0`, `\\`, `\"`, `\$` and
+/// `\u{...}` with one to six hex digits naming a Unicode scalar value.
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut string = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => string.push('\n'),
+            Some('t') => string.push('\t'),
+            Some('r') => string.push('\r'),
+            Some('0') => string.push('\0'),
+            Some('\\') => string.push('\\'),
+            Some('"') => string.push('"'),
+            Some('$') => string.push('$'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let digits = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(digits, _)| digits)
+                    .filter(|digits| (1..=6).contains(&digits.len()))
+                    .ok_or("Invalid unicode escape.".to_string())?;
+                let code_point = u32::from_str_radix(digits, 16)
+                    .map_err(|_| "Invalid unicode escape.".to_string())?;
+                let c = char::from_u32(code_point)
+                    .ok_or(format!("Invalid code point '\\u{{{digits}}}'."))?;
+                string.push(c);
+                chars = rest[digits.len() + 2..].chars();
+            }
+            Some(c) => return Err(format!("Invalid escape sequence '\\{c}'.")),
+            None => return Err("Invalid escape sequence '\\'.".to_string()),
         }
     }
+    Ok(string)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::chunk::Chunk;
-    use crate::compiler::Compiler;
+    use crate::chunk::{Chunk, ChunkBuilder};
+    use crate::compiler::{unescape, Compiler};
     use crate::object::{Object, ObjectType};
     use crate::op_code::OpCode;
     use crate::op_code::OpCode::{
-        OP_ADD, OP_EQUAL, OP_GREATER, OP_MULTIPLY, OP_NEGATE, OP_NIL, OP_NOT, OP_RETURN,
-        OP_SUBTRACT,
+        OP_ADD, OP_CONSTANT, OP_DEFINE_GLOBAL, OP_EQUAL, OP_GREATER, OP_LESS, OP_MULTIPLY,
+        OP_NEGATE, OP_NIL, OP_NOT, OP_POP, OP_POPN, OP_PRINT, OP_RETURN, OP_SUBTRACT,
     };
     use crate::value::Value;
 
+    #[test]
+    fn compile_to_chunk_should_succeed() {
+        let chunk = Compiler::compile_to_chunk("1 < 2").unwrap();
+
+        let expected = ChunkBuilder::new()
+            .constant(Value::VAL_NUMBER(1.0))
+            .constant(Value::VAL_NUMBER(2.0))
+            .op(OP_LESS)
+            .op(OP_RETURN)
+            .build();
+        assert_eq!(chunk, expected);
+    }
+
+    #[test]
+    fn implicit_return_should_succeed() {
+        let mut chunk = Chunk::default();
+        let mut compiler = Compiler::new(&mut chunk).with_implicit_return(false);
+
+        assert!(compiler.compile("var a = 1; fun f() {}".to_string().into_bytes()));
+        // defining `f` is the last instruction
+        assert_eq!(
+            chunk.code[chunk.code.len() - 2..],
+            [OP_DEFINE_GLOBAL.into(), 2]
+        );
+
+        // the function still ends with its own return
+        let function = chunk
+            .constants
+            .iter()
+            .find_map(|constant| match constant {
+                Value::VAL_OBJECT(Object {
+                    object_type: ObjectType::OBJ_FUNCTION(function),
+                }) => Some(function),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(function.chunk.code.last(), Some(&OP_RETURN.into()));
+    }
+
+    #[test]
+    fn source_map_should_succeed() {
+        let source = "var a = 2;\nprint 1 + a;";
+        let mut chunk = Chunk::default();
+        let mut compiler = Compiler::new(&mut chunk).with_source_map(true);
+        assert!(compiler.compile(source.to_string().into_bytes()));
+
+        // ends with add, print 1, return
+        let add = chunk.code.len() - 4;
+        assert_eq!(chunk.code[add], OP_ADD.into());
+        assert_eq!(&source[chunk.span_of(add).unwrap()], "+");
+
+        let print = add + 1;
+        assert_eq!(chunk.code[print], OP_PRINT.into());
+        assert_eq!(&source[chunk.span_of(print).unwrap()], ";");
+
+        // nothing is recorded unless asked for
+        let chunk = Compiler::compile_to_chunk(source).unwrap();
+        assert_eq!(chunk.span_of(add), None);
+    }
+
+    #[test]
+    fn compile_to_chunk_equality_should_succeed() {
+        let source = "var a = \"a\";\n{ var b = [a, 1]; print b[0] + \"b\"; }";
+
+        let first = Compiler::compile_to_chunk(source).unwrap();
+        let second = Compiler::compile_to_chunk(source).unwrap();
+        assert_eq!(first, second);
+
+        let other = Compiler::compile_to_chunk("var a = \"b\";").unwrap();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn compile_to_chunk_should_fail() {
+        let errors = Compiler::compile_to_chunk("1 +").err().unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 1);
+    }
+
     #[test]
     fn parse_precedence_number_order_should_succeed() {
         let code = "-54.55 * (2.0 + 6)"; // -a.b * (c + d)
@@ -275,19 +1629,13 @@ mod tests {
         let result = compiler.compile(code.to_string().into_bytes());
         assert!(result);
 
-        // chunk constants
-        assert_eq!(chunk.constants.values[0], Value::VAL_NUMBER(54.55));
-        assert_eq!(chunk.constants.values[1], Value::VAL_NUMBER(2.0));
-        assert_eq!(chunk.constants.values[2], Value::VAL_NUMBER(6.0));
+        // literal arithmetic is folded, in precedence order
+        assert_eq!(chunk.constants.len(), 1);
+        assert_eq!(chunk.constants[0], Value::VAL_NUMBER(-54.55 * (2.0 + 6.0)));
 
         // chunk code instructions
         assert_eq!(chunk.code[0..2], [0, 0]);
-        assert_eq!(chunk.code[2], OP_NEGATE.into());
-        assert_eq!(chunk.code[3..5], [0, 1]);
-        assert_eq!(chunk.code[5..7], [0, 2]);
-        assert_eq!(chunk.code[7], OP_ADD.into());
-        assert_eq!(chunk.code[8], OP_MULTIPLY.into());
-        assert_eq!(chunk.code[9], OP_RETURN.into());
+        assert_eq!(chunk.code[2], OP_RETURN.into());
     }
 
     #[test]
@@ -300,23 +1648,17 @@ mod tests {
         assert!(result);
 
         // chunk constants
-        assert_eq!(chunk.constants.values[0], Value::VAL_NUMBER(5.0));
-        assert_eq!(chunk.constants.values[1], Value::VAL_NUMBER(4.0));
-        assert_eq!(chunk.constants.values[2], Value::VAL_NUMBER(3.0));
-        assert_eq!(chunk.constants.values[3], Value::VAL_NUMBER(2.0));
+        assert_eq!(chunk.constants[0], Value::VAL_NUMBER(1.0));
+        assert_eq!(chunk.constants[1], Value::VAL_NUMBER(6.0));
 
         assert_eq!(chunk.code[0..2], [0, 0]);
         assert_eq!(chunk.code[2..4], [0, 1]);
-        assert_eq!(chunk.code[4], OP_SUBTRACT.into());
-        assert_eq!(chunk.code[5..7], [0, 2]);
-        assert_eq!(chunk.code[7..9], [0, 3]);
-        assert_eq!(chunk.code[9], OP_MULTIPLY.into());
-        assert_eq!(chunk.code[10], OP_GREATER.into());
-        assert_eq!(chunk.code[11], OP_NIL.into());
-        assert_eq!(chunk.code[12], OP_NOT.into());
-        assert_eq!(chunk.code[13], OP_EQUAL.into());
-        assert_eq!(chunk.code[14], OP_NOT.into());
-        assert_eq!(chunk.code[15], OP_RETURN.into());
+        assert_eq!(chunk.code[4], OP_GREATER.into());
+        assert_eq!(chunk.code[5], OP_NIL.into());
+        assert_eq!(chunk.code[6], OP_NOT.into());
+        assert_eq!(chunk.code[7], OP_EQUAL.into());
+        assert_eq!(chunk.code[8], OP_NOT.into());
+        assert_eq!(chunk.code[9], OP_RETURN.into());
     }
 
     #[test]
@@ -330,10 +1672,10 @@ mod tests {
 
         // chunk constants
         let string = Value::VAL_OBJECT(Object {
-            object_type: ObjectType::OBJ_STRING(String::from(r#""test""#)),
+            object_type: ObjectType::OBJ_STRING("test".into()),
         });
-        assert_eq!(chunk.constants.values[0], string);
-        assert_eq!(chunk.constants.values[1], string);
+        assert_eq!(chunk.constants[0], string);
+        assert_eq!(chunk.constants[1], string);
 
         assert_eq!(chunk.code[0..2], [0, 0]);
         assert_eq!(chunk.code[2..4], [0, 1]);
@@ -352,21 +1694,21 @@ mod tests {
 
         // chunk constants
         assert_eq!(
-            chunk.constants.values[0],
+            chunk.constants[0],
             Value::VAL_OBJECT(Object {
-                object_type: ObjectType::OBJ_STRING(String::from(r#""st""#)),
+                object_type: ObjectType::OBJ_STRING("st".into()),
             })
         );
         assert_eq!(
-            chunk.constants.values[1],
+            chunk.constants[1],
             Value::VAL_OBJECT(Object {
-                object_type: ObjectType::OBJ_STRING(String::from(r#""ri""#)),
+                object_type: ObjectType::OBJ_STRING("ri".into()),
             })
         );
         assert_eq!(
-            chunk.constants.values[2],
+            chunk.constants[2],
             Value::VAL_OBJECT(Object {
-                object_type: ObjectType::OBJ_STRING(String::from(r#""ng""#)),
+                object_type: ObjectType::OBJ_STRING("ng".into()),
             })
         );
 
@@ -377,4 +1719,147 @@ mod tests {
         assert_eq!(chunk.code[7], OP_ADD.into());
         assert_eq!(chunk.code[8], OP_RETURN.into());
     }
+
+    #[test]
+    fn constant_folding_should_succeed() {
+        let chunk = Compiler::compile_to_chunk("2 + 3").unwrap();
+
+        assert_eq!(chunk.constants.values, [Value::VAL_NUMBER(5.0)]);
+        assert_eq!(chunk.code, [OP_CONSTANT.into(), 0, OP_RETURN.into()]);
+
+        let chunk = Compiler::compile_to_chunk("-(2 + 3 * 4) - -1").unwrap();
+        assert_eq!(chunk.constants.values, [Value::VAL_NUMBER(-13.0)]);
+        assert!(!chunk.code.contains(&OP_NEGATE.into()));
+    }
+
+    #[test]
+    fn negate_literal_should_succeed() {
+        let chunk = Compiler::compile_to_chunk("-5").unwrap();
+
+        assert_eq!(chunk.constants.values, [Value::VAL_NUMBER(-5.0)]);
+        assert_eq!(chunk.code, [OP_CONSTANT.into(), 0, OP_RETURN.into()]);
+
+        let chunk = Compiler::compile_to_chunk("--5").unwrap();
+        assert_eq!(chunk.constants.values, [Value::VAL_NUMBER(5.0)]);
+        assert_eq!(chunk.code, [OP_CONSTANT.into(), 0, OP_RETURN.into()]);
+    }
+
+    #[test]
+    fn constant_folding_should_fail() {
+        // only literal numbers fold, anything else is left for the VM to check
+        for (source, op_code) in [
+            ("1 + \"a\"", OP_ADD),
+            ("nil - 1", OP_SUBTRACT),
+            ("-true", OP_NEGATE),
+            ("var a = 1; a * 2;", OP_MULTIPLY),
+        ] {
+            let chunk = Compiler::compile_to_chunk(source).unwrap();
+            assert!(chunk.code.contains(&op_code.into()), "{source}");
+        }
+    }
+
+    #[test]
+    fn end_scope_popn_should_succeed() {
+        let chunk = Compiler::compile_to_chunk("{ var a = 1; var b = 2; var c = 3; }").unwrap();
+
+        assert_eq!(chunk.code[0..6], [0, 0, 0, 1, 0, 2]);
+        assert_eq!(chunk.code[6..8], [OP_POPN.into(), 3]);
+        assert!(!chunk.code[8..].contains(&OP_POP.into()));
+    }
+
+    #[test]
+    fn compile_twice_should_succeed() {
+        let mut chunk = Chunk::default();
+        let mut compiler = Compiler::new(&mut chunk);
+
+        assert!(compiler.compile("1<1".to_string().into_bytes()));
+        assert!(compiler.compile("2<2".to_string().into_bytes()));
+
+        assert_eq!(chunk.constants.values, [Value::VAL_NUMBER(2.0)]);
+
+        assert_eq!(chunk.code.len(), 6);
+        assert_eq!(chunk.code[0..2], [0, 0]);
+        assert_eq!(chunk.code[2..4], [0, 0]);
+        assert_eq!(chunk.code[4], OP_LESS.into());
+        assert_eq!(chunk.code[5], OP_RETURN.into());
+    }
+
+    #[test]
+    fn unescape_should_succeed() {
+        assert_eq!(unescape(r#"a\tb\n\"c\" \\"#).unwrap(), "a\tb\n\"c\" \\");
+        assert_eq!(unescape(r"\u{1F600}!").unwrap(), "\u{1F600}!");
+        assert_eq!(unescape(r"\u{e9}\u{0041}").unwrap(), "éA");
+
+        let chunk = Compiler::compile_to_chunk(r#""\u{1F600}""#).unwrap();
+        assert_eq!(
+            chunk.constants[0],
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING("\u{1F600}".into()),
+            })
+        );
+    }
+
+    #[test]
+    fn unescape_should_fail() {
+        for (raw, expected) in [
+            (r"\u{110000}", "Invalid code point '\\u{110000}'."),
+            (r"\u{D800}", "Invalid code point '\\u{D800}'."),
+            (r"\u{}", "Invalid unicode escape."),
+            (r"\u{1234567}", "Invalid unicode escape."),
+            (r"\u{zz}", "Invalid unicode escape."),
+            (r"\u1F600", "Invalid unicode escape."),
+            (r"\q", "Invalid escape sequence '\\q'."),
+        ] {
+            assert_eq!(unescape(raw).unwrap_err(), expected, "{raw}");
+        }
+
+        let errors = Compiler::compile_to_chunk(r#"print "\u{110000}";"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].message, "Invalid code point '\\u{110000}'.");
+    }
+
+    #[test]
+    fn interpolation_should_fail() {
+        let errors = Compiler::compile_to_chunk(r#"print "a ${1;"#)
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors[0].message,
+            "Expected '}' after interpolated expression."
+        );
+
+        let errors = Compiler::compile_to_chunk(r#"print "a ${1} b;"#)
+            .err()
+            .unwrap();
+        assert_eq!(errors[0].message, "Unterminated string.");
+    }
+
+    #[test]
+    fn collect_errors_should_fail() {
+        let mut chunk = Chunk::default();
+        let mut compiler = Compiler::new(&mut chunk);
+
+        let errors = compiler.collect_errors(
+            "var = 1;\nprint 1 +;\nprint 2;\nclass {}"
+                .to_string()
+                .into_bytes(),
+        );
+        let errors = errors
+            .iter()
+            .map(|error| (error.message.as_str(), error.line, error.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                ("Expected variable name.", 1, 5),
+                ("Expected expression.", 2, 10),
+                ("Expected class name.", 4, 7),
+            ]
+        );
+
+        assert!(compiler
+            .collect_errors("print 1;".to_string().into_bytes())
+            .is_empty());
+    }
 }
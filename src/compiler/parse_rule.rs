@@ -7,12 +7,22 @@ use crate::token::{TokenType, TokenType::*};
 #[derive(Debug, PartialEq)]
 pub enum ParseFn {
     Binary,
+    Call,
+    Dot,
     Grouping,
+    Index,
+    Interpolation,
+    Lambda,
+    List,
     Literal,
+    Map,
     Null,
     Number,
     String,
+    Super,
+    This,
     Unary,
+    Variable,
 }
 
 #[derive(Debug)]
@@ -27,8 +37,8 @@ impl ParseRule {
         match *token_type {
             TOKEN_LEFT_PAREN => ParseRule {
                 prefix: ParseFn::Grouping,
-                infix: ParseFn::Null,
-                precedence: Precedence::PREC_NONE,
+                infix: ParseFn::Call,
+                precedence: Precedence::PREC_CALL,
             },
             TOKEN_RIGHT_PAREN => ParseRule {
                 prefix: ParseFn::Null,
@@ -36,7 +46,7 @@ impl ParseRule {
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_LEFT_BRACE => ParseRule {
-                prefix: ParseFn::Null,
+                prefix: ParseFn::Map,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
@@ -45,16 +55,31 @@ impl ParseRule {
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
-            TOKEN_COMMA => ParseRule {
+            TOKEN_LEFT_BRACKET => ParseRule {
+                prefix: ParseFn::List,
+                infix: ParseFn::Index,
+                precedence: Precedence::PREC_CALL,
+            },
+            TOKEN_RIGHT_BRACKET => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
-            TOKEN_DOT => ParseRule {
+            TOKEN_COLON => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
+            TOKEN_COMMA => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_DOT => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Dot,
+                precedence: Precedence::PREC_CALL,
+            },
             TOKEN_MINUS => ParseRule {
                 prefix: ParseFn::Unary,
                 infix: ParseFn::Binary,
@@ -120,16 +145,31 @@ impl ParseRule {
                 infix: ParseFn::Binary,
                 precedence: Precedence::PREC_COMPARISON,
             },
-            TOKEN_IDENTIFIER => ParseRule {
+            TOKEN_ELLIPSIS => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
+            TOKEN_ARROW => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_IDENTIFIER => ParseRule {
+                prefix: ParseFn::Variable,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
             TOKEN_STRING => ParseRule {
                 prefix: ParseFn::String,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_INTERPOLATION => ParseRule {
+                prefix: ParseFn::Interpolation,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
             TOKEN_NUMBER => ParseRule {
                 prefix: ParseFn::Number,
                 infix: ParseFn::Null,
@@ -140,11 +180,31 @@ impl ParseRule {
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_ASSERT => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
+            TOKEN_BREAK => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
             TOKEN_CLASS => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_CONST => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
+            TOKEN_CONTINUE => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
             TOKEN_ELSE => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
@@ -161,7 +221,7 @@ impl ParseRule {
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_FUN => ParseRule {
-                prefix: ParseFn::Null,
+                prefix: ParseFn::Lambda,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
@@ -170,6 +230,21 @@ impl ParseRule {
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_IN => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
+            TOKEN_DOT_DOT => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Binary,
+                precedence: Precedence::PREC_RANGE,
+            },
+            TOKEN_IS => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Binary,
+                precedence: Precedence::PREC_COMPARISON,
+            },
             TOKEN_NIL => ParseRule {
                 prefix: ParseFn::Literal,
                 infix: ParseFn::Null,
@@ -191,22 +266,22 @@ impl ParseRule {
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_SUPER => ParseRule {
-                prefix: ParseFn::Null,
+                prefix: ParseFn::Super,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_THIS => ParseRule {
-                prefix: ParseFn::Null,
+                prefix: ParseFn::This,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_TRUE => ParseRule {
-                prefix: ParseFn::Null,
+                prefix: ParseFn::Literal,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
             TOKEN_VAR => ParseRule {
-                prefix: ParseFn::Literal,
+                prefix: ParseFn::Null,
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
@@ -215,6 +290,11 @@ impl ParseRule {
                 infix: ParseFn::Null,
                 precedence: Precedence::PREC_NONE,
             },
+            TOKEN_COMMENT(_) => ParseRule {
+                prefix: ParseFn::Null,
+                infix: ParseFn::Null,
+                precedence: Precedence::PREC_NONE,
+            },
             TOKEN_ERROR => ParseRule {
                 prefix: ParseFn::Null,
                 infix: ParseFn::Null,
@@ -13,7 +13,7 @@ impl Parser {
             current: None,
             previous: None,
             had_error: false,
-            panic_mode: true,
+            panic_mode: false,
         }
     }
 }
@@ -15,6 +15,8 @@ pub enum Precedence {
     PREC_EQUALITY,
     /// < > <= >=
     PREC_COMPARISON,
+    /// ..
+    PREC_RANGE,
     /// + -
     PREC_TERM,
     /// * /
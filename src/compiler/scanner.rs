@@ -4,16 +4,29 @@ use std::slice::Iter;
 use std::thread::{current, scope};
 
 use crate::token::TokenType::*;
-use crate::token::{Token, TokenType};
+use crate::token::{CommentType, Token, TokenType};
 
 type PeekableToken<'a> = Peekable<slice::Iter<'a, &'a u8>>;
 
+/// Where a scanner is, saved before looking ahead and restored to scan the same tokens again.
+pub(crate) struct Checkpoint {
+    start: usize,
+    current: usize,
+    line: usize,
+    is_finished: bool,
+    interpolations: Vec<usize>,
+}
+
 pub struct Scanner {
-    pub(crate) source: Vec<u8>,
+    source: Vec<u8>,
     start: usize,
     pub(crate) current: usize,
     line: usize,
     is_finished: bool,
+    /// for each `${` not yet closed, how many `{` inside it are still open
+    interpolations: Vec<usize>,
+    /// scans comments as `TOKEN_COMMENT` instead of skipping them like whitespace
+    comments: bool,
 }
 
 impl Scanner {
@@ -24,45 +37,151 @@ impl Scanner {
             current: 0,
             line: 1,
             is_finished: false,
+            interpolations: vec![],
+            comments: false,
         }
     }
 
-    pub fn scan_token(&mut self) -> Option<Token> {
-        self.start = self.current;
-
-        let source = self.source.clone();
-        let current_token = source.iter().skip(self.start).collect::<Vec<_>>();
+    pub fn with_comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
+    }
 
-        let mut current_token = current_token.iter().peekable();
+    /// Replaces the source and rewinds every position so the scanner can be reused.
+    pub fn reset(&mut self, source: Vec<u8>) {
+        self.source = source;
+        self.reset_position();
+    }
 
-        self.skip_whitespace(&mut current_token);
+    fn reset_position(&mut self) {
+        self.start = 0;
+        self.current = 0;
+        self.line = 1;
+        self.is_finished = false;
+        self.interpolations.clear();
+    }
 
-        if self.is_finished {
-            return None;
+    /// Continues scanning from byte `offset` of the source, as if every token before it had just
+    /// been scanned. After an edit, `reset` to the new source and resume from the last token
+    /// boundary before the edit instead of collecting every token again.
+    ///
+    /// `offset` must be a token boundary: the start of a token, or whitespace between tokens,
+    /// including those inside a `${...}`. Resuming inside a string, comment or multibyte
+    /// character scans different tokens than a full scan would. The source before `offset` is
+    /// scanned again, without keeping its tokens, to recount the line and find the
+    /// interpolations still open there.
+    pub fn rescan_from(&mut self, offset: usize) {
+        let offset = offset.min(self.source.len());
+        self.reset_position();
+        while self.current < offset {
+            let checkpoint = self.checkpoint();
+            self.scan_token();
+            if self.current > offset {
+                // the token after the whitespace at `offset`, the newlines up to it still count
+                self.restore(checkpoint);
+                self.line += self.source[self.current..offset]
+                    .iter()
+                    .filter(|&&c| c == b'\n')
+                    .count();
+                break;
+            }
         }
+        self.start = offset;
+        self.current = offset;
+        self.is_finished = false;
+    }
 
-        if self.is_at_end(&mut current_token) {
-            self.is_finished = true;
-            return Some(self.make_token(TOKEN_EOF));
+    pub fn scan_token(&mut self) -> Option<Token> {
+        // skipped comments loop back here rather than recursing, a file of them can be long
+        loop {
+            self.start = self.current;
+            self.skip_whitespace();
+
+            if self.is_finished {
+                return None;
+            }
+
+            if self.is_at_end() {
+                self.is_finished = true;
+                return Some(self.make_token(TOKEN_EOF));
+            }
+
+            if !self.source[self.current..].starts_with(b"//")
+                && !self.source[self.current..].starts_with(b"/*")
+            {
+                break;
+            }
+            let token = self.comment();
+            if self.comments || token.token_type == TOKEN_ERROR {
+                return Some(token);
+            }
         }
 
+        let source = self.source.clone();
+        let current_token = source.iter().skip(self.start).collect::<Vec<_>>();
+
+        let mut current_token = current_token.iter().peekable();
+
         if let Some(&c) = current_token.peek() {
             self.start = self.current;
             if Self::is_digit(c) {
                 return Some(self.number(&mut current_token));
             };
-            if Self::is_alpha(c) {
+            if self
+                .char_at(self.current)
+                .is_some_and(Self::is_identifier_start)
+            {
                 return Some(self.identifier(&mut current_token));
             };
+            if !c.is_ascii() {
+                // skip the whole character so its continuation bytes aren't reported again
+                let len = self.char_at(self.current).map_or(1, char::len_utf8);
+                for _ in 0..len {
+                    self.advance();
+                    current_token.next();
+                }
+                return Some(match len {
+                    1 => self.error_token("Invalid UTF-8."),
+                    _ => self.error_token("Unexpected character."),
+                });
+            }
             self.advance();
             current_token.next();
             let token_type = match **c as char {
                 '(' => TOKEN_LEFT_PAREN,
                 ')' => TOKEN_RIGHT_PAREN,
-                '{' => TOKEN_LEFT_BRACE,
-                '}' => TOKEN_RIGHT_BRACE,
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    TOKEN_LEFT_BRACE
+                }
+                // the `}` closing an interpolation continues its string
+                '}' => match self.interpolations.last_mut() {
+                    Some(0) => {
+                        self.interpolations.pop();
+                        return Some(self.string(&mut current_token));
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        TOKEN_RIGHT_BRACE
+                    }
+                    None => TOKEN_RIGHT_BRACE,
+                },
+                '[' => TOKEN_LEFT_BRACKET,
+                ']' => TOKEN_RIGHT_BRACKET,
                 ';' => TOKEN_SEMICOLON,
+                ':' => TOKEN_COLON,
                 ',' => TOKEN_COMMA,
+                '.' if self.source[self.current..].starts_with(b"..") => {
+                    self.advance();
+                    self.advance();
+                    TOKEN_ELLIPSIS
+                }
+                '.' if self.source.get(self.current) == Some(&b'.') => {
+                    self.advance();
+                    TOKEN_DOT_DOT
+                }
                 '.' => TOKEN_DOT,
                 '-' => TOKEN_MINUS,
                 '+' => TOKEN_PLUS,
@@ -78,6 +197,8 @@ impl Scanner {
                 '=' => {
                     if self.match_token('=') {
                         TOKEN_EQUAL_EQUAL
+                    } else if self.match_token('>') {
+                        TOKEN_ARROW
                     } else {
                         TOKEN_EQUAL
                     }
@@ -96,6 +217,9 @@ impl Scanner {
                         TOKEN_GREATER
                     }
                 }
+                '"' if self.source[self.current..].starts_with(b"\"\"") => {
+                    return Some(self.triple_quoted_string(&mut current_token))
+                }
                 '"' => return Some(self.string(&mut current_token)),
                 _ => return Some(self.error_token("Unexpected character.")),
             };
@@ -124,62 +248,128 @@ impl Scanner {
         true
     }
 
-    fn is_at_end(&self, token: &mut PeekableToken) -> bool {
+    fn is_at_end(&self) -> bool {
         self.current == self.source.len()
     }
 
-    fn make_token(&self, token_type: TokenType) -> Token {
-        let message = self.source[self.start..self.current].to_vec();
-        let message = String::from_utf8(message).unwrap();
-        Token::new(token_type, message, self.start, self.line)
+    /// Scans the next token without consuming it.
+    pub fn peek_token(&mut self) -> Option<Token> {
+        let checkpoint = self.checkpoint();
+        let token = self.scan_token();
+        self.restore(checkpoint);
+        token
     }
 
-    fn error_token(&self, message: &str) -> Token {
-        Token {
-            token_type: TOKEN_ERROR,
-            message: message.to_string(),
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
             start: self.start,
+            current: self.current,
             line: self.line,
+            is_finished: self.is_finished,
+            interpolations: self.interpolations.clone(),
         }
     }
 
-    fn skip_whitespace(&mut self, token: &mut PeekableToken) {
-        while let Some(&c) = token.peek() {
-            match **c as char {
-                ' ' | '\r' | '\t' => {
-                    self.advance();
-                    self.start = self.current;
-                    token.next();
-                }
+    pub(crate) fn restore(&mut self, checkpoint: Checkpoint) {
+        self.start = checkpoint.start;
+        self.current = checkpoint.current;
+        self.line = checkpoint.line;
+        self.is_finished = checkpoint.is_finished;
+        self.interpolations = checkpoint.interpolations;
+    }
+
+    /// Byte offset in the source where scanning resumes, just past the last scanned token.
+    pub fn current_offset(&self) -> usize {
+        self.current
+    }
+
+    pub fn source(&self) -> &[u8] {
+        &self.source
+    }
+
+    fn make_token(&self, token_type: TokenType) -> Token {
+        Token::new(
+            token_type,
+            self.start,
+            self.current - self.start,
+            self.line,
+            self.column(),
+        )
+    }
+
+    /// 1-based column of the current lexeme on its line, counted in characters.
+    fn column(&self) -> usize {
+        let line_start = self.source[..self.start]
+            .iter()
+            .rposition(|&c| c == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let before = &self.source[line_start..self.start];
+        let chars = match std::str::from_utf8(before) {
+            Ok(before) => before.chars().count(),
+            Err(_) => before.len(),
+        };
+        chars + 1
+    }
+
+    /// Decodes the character starting at byte `offset`, `None` past the end or on invalid UTF-8.
+    fn char_at(&self, offset: usize) -> Option<char> {
+        let end = self.source.len().min(offset + 4);
+        let bytes = self.source.get(offset..end)?;
+        bytes.utf8_chunks().next()?.valid().chars().next()
+    }
+
+    fn error_token(&self, message: &'static str) -> Token {
+        Token {
+            message: Some(message),
+            ..Token::new(TOKEN_ERROR, self.start, 0, self.line, self.column())
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(&c) = self.source.get(self.current) {
+            match c as char {
+                ' ' | '\r' | '\t' => self.advance(),
                 '\n' => {
                     self.line += 1;
                     self.advance();
-                    self.start = self.current;
-                    token.next();
                 }
-                '/' => {
-                    self.advance();
-                    token.next();
-                    if let Some(&next) = token.peek() {
-                        self.advance();
-                        token.next();
-                        if **next as char == '/' {
-                            while let Some(&next) = token.peek() {
-                                if **next as char != '\n' {
-                                    self.advance();
-                                    token.next();
-                                }
-                            }
-                        }
-                    } else {
-                        return;
-                    }
+                _ => break,
+            }
+        }
+        self.start = self.current;
+    }
+
+    /// Scans a `//` comment up to the end of its line, or a `/*` comment up to the next `*/`.
+    fn comment(&mut self) -> Token {
+        let comment_type = if self.source[self.current..].starts_with(b"//") {
+            while self.source.get(self.current).is_some_and(|&c| c != b'\n') {
+                self.advance();
+            }
+            CommentType::COMMENT_LINE
+        } else {
+            self.current += 2;
+            while !self.source[self.current..].starts_with(b"*/") {
+                let Some(&c) = self.source.get(self.current) else {
+                    return self.error_token("Unterminated comment.");
+                };
+                self.advance();
+                if c == b'\n' {
+                    self.line += 1;
                 }
-                _ => return,
             }
+            self.current += 2;
+            CommentType::COMMENT_BLOCK
+        };
+
+        if std::str::from_utf8(&self.source[self.start..self.current]).is_err() {
+            return self.error_token("Invalid UTF-8.");
         }
+        self.make_token(TOKEN_COMMENT(comment_type))
     }
 
+    /// Scans up to the closing `"`, or up to a `${` which makes the string so far an interpolation
+    /// token. The tokens of the embedded expression follow, and the `}` closing it resumes the
+    /// string, so `"a ${b} c"` scans as `"a ${`, `b` and `} c"`.
     fn string(&mut self, token: &mut PeekableToken) -> Token {
         loop {
             if let Some(&c) = token.peek() {
@@ -188,14 +378,74 @@ impl Scanner {
                 if **c as char == '"' {
                     break;
                 }
+                if **c as char == '$' && self.source.get(self.current) == Some(&b'{') {
+                    self.advance();
+                    token.next();
+                    self.interpolations.push(0);
+                    return self.make_token(TOKEN_INTERPOLATION);
+                }
                 if **c as char == '\n' {
                     self.line += 1;
                 }
+                // an escaped character never ends the string, the compiler decodes it
+                if **c as char == '\\' {
+                    if let Some(&&&escaped) = token.peek() {
+                        self.advance();
+                        token.next();
+                        if escaped == b'\n' {
+                            self.line += 1;
+                        }
+                    }
+                }
             } else {
                 return self.error_token("Unterminated string.");
             }
         }
 
+        if std::str::from_utf8(&self.source[self.start..self.current]).is_err() {
+            return self.error_token("Invalid UTF-8.");
+        }
+        self.make_token(TOKEN_STRING)
+    }
+
+    /// A `"""` string, which runs until the next `"""` and can span lines like any string.
+    /// Escapes work the same as in a `"` string, but `${` is kept as it is.
+    fn triple_quoted_string(&mut self, token: &mut PeekableToken) -> Token {
+        for _ in 0..2 {
+            self.advance();
+            token.next();
+        }
+
+        loop {
+            if self.source[self.current..].starts_with(b"\"\"\"") {
+                for _ in 0..3 {
+                    self.advance();
+                    token.next();
+                }
+                break;
+            }
+            let Some(&&&c) = token.peek() else {
+                return self.error_token("Unterminated string.");
+            };
+            self.advance();
+            token.next();
+            if c == b'\n' {
+                self.line += 1;
+            }
+            if c == b'\\' {
+                if let Some(&&&escaped) = token.peek() {
+                    self.advance();
+                    token.next();
+                    if escaped == b'\n' {
+                        self.line += 1;
+                    }
+                }
+            }
+        }
+
+        if std::str::from_utf8(&self.source[self.start..self.current]).is_err() {
+            return self.error_token("Invalid UTF-8.");
+        }
         self.make_token(TOKEN_STRING)
     }
 
@@ -232,74 +482,34 @@ impl Scanner {
     }
 
     fn identifier(&mut self, token: &mut PeekableToken) -> Token {
-        let token = self.identifier_type(token);
-        self.make_token(token)
+        while let Some(c) = self.char_at(self.current) {
+            if !Self::is_identifier_start(c) && !c.is_numeric() {
+                break;
+            }
+            // a character is one to four bytes
+            for _ in 0..c.len_utf8() {
+                self.advance();
+                token.next();
+            }
+        }
+
+        let token_type = self.identifier_type();
+        self.make_token(token_type)
     }
 
-    fn identifier_type(&mut self, token: &mut PeekableToken) -> TokenType {
-        if let Some(&c) = token.peek() {
-            match **c as char {
-                'a' => return self.check_keyword(1, "nd", TOKEN_AND),
-                'c' => return self.check_keyword(1, "lass", TOKEN_CLASS),
-                'e' => return self.check_keyword(1, "lse", TOKEN_ELSE),
-                'f' => {
-                    self.advance();
-                    token.next();
-                    if let Some(&c) = token.peek() {
-                        match **c as char {
-                            'a' => return self.check_keyword(2, "lse", TOKEN_FALSE),
-                            'o' => return self.check_keyword(2, "r", TOKEN_FOR),
-                            'u' => return self.check_keyword(2, "n", TOKEN_FUN),
-                            _ => {}
-                        }
-                    }
-                }
-                'i' => return self.check_keyword(1, "f", TOKEN_IF),
-                'n' => return self.check_keyword(1, "il", TOKEN_NIL),
-                'o' => return self.check_keyword(1, "r", TOKEN_OR),
-                'p' => return self.check_keyword(1, "rint", TOKEN_PRINT),
-                'r' => return self.check_keyword(1, "eturn", TOKEN_RETURN),
-                's' => return self.check_keyword(1, "uper", TOKEN_SUPER),
-                'v' => return self.check_keyword(1, "ar", TOKEN_VAR),
-                'w' => return self.check_keyword(1, "hile", TOKEN_WHILE),
-                't' => {
-                    self.advance();
-                    token.next();
-                    if let Some(&c) = token.peek() {
-                        match **c as char {
-                            'h' => return self.check_keyword(2, "is", TOKEN_THIS),
-                            'r' => return self.check_keyword(2, "ue", TOKEN_TRUE),
-                            _ => {}
-                        }
-                    }
-                }
-                _ => {}
-            };
-        }
-        self.advance();
-        TOKEN_IDENTIFIER
+    fn identifier_type(&self) -> TokenType {
+        std::str::from_utf8(&self.source[self.start..self.current])
+            .ok()
+            .and_then(TokenType::keyword_from_str)
+            .unwrap_or(TOKEN_IDENTIFIER)
     }
 
     fn is_digit(c: &u8) -> bool {
         (*c as char).is_ascii_digit()
     }
 
-    fn is_alpha(c: &u8) -> bool {
-        (*c as char).is_alphabetic()
-    }
-
-    fn check_keyword(&mut self, start: usize, rest: &str, token_type: TokenType) -> TokenType {
-        let length = rest.len();
-        let left = self.start + start;
-        let right = left + length;
-        self.current = right;
-
-        let possible_rest = String::from_utf8(self.source[left..right].to_vec()).unwrap();
-        if &*possible_rest == rest {
-            return token_type;
-        }
-
-        TOKEN_IDENTIFIER
+    fn is_identifier_start(c: char) -> bool {
+        c.is_alphabetic() || c == '_'
     }
 }
 
@@ -319,7 +529,7 @@ mod tests {
 
         let token = token.unwrap();
         assert_eq!(token.token_type, token_type);
-        assert_eq!(token.message, token_message.to_string());
+        assert_eq!(token.lexeme(&scanner.source), token_message);
         assert_eq!(token.start, start_buffer);
         assert_eq!(token.line, line);
     }
@@ -336,6 +546,70 @@ mod tests {
         assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 9, 1);
     }
 
+    #[test]
+    fn arrow_should_succeed() {
+        let source = "=> >= == = x".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_ARROW, "=>", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_GREATER_EQUAL, ">=", 3, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EQUAL_EQUAL, "==", 6, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EQUAL, "=", 9, 1);
+    }
+
+    #[test]
+    fn checkpoint_should_succeed() {
+        let source = r#"a "${b}" c"#.to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+        scanner.scan_token();
+        scanner.scan_token();
+
+        // looking ahead from inside an interpolation resumes it afterwards
+        let checkpoint = scanner.checkpoint();
+        let ahead = (0..3).map(|_| scanner.scan_token()).collect::<Vec<_>>();
+        scanner.restore(checkpoint);
+        let again = (0..3).map(|_| scanner.scan_token()).collect::<Vec<_>>();
+
+        assert_eq!(ahead, again);
+        assert_eq!(again[1].unwrap().token_type, TokenType::TOKEN_STRING);
+    }
+
+    #[test]
+    fn span_should_succeed() {
+        let mut scanner = Scanner::new("var x".to_string().into_bytes());
+
+        assert_eq!(scanner.scan_token().unwrap().span(), 0..3);
+        assert_eq!(scanner.current_offset(), 3);
+        let x = scanner.scan_token().unwrap();
+        assert_eq!(x.span(), 4..5);
+        assert_eq!(&scanner.source()[x.span()], b"x");
+        assert_eq!(scanner.current_offset(), 5);
+    }
+
+    #[test]
+    fn rescan_from_should_succeed() {
+        let source = "var a = \"one\ntwo\";\nprint a;\n  class B {}\nvar c = [1, 2];\n\
+            print \"x ${ {1: \"${ a }\"}[1] } y ${\n b }\";";
+        let mut scanner = Scanner::new(source.to_string().into_bytes());
+        let tokens = std::iter::from_fn(|| scanner.scan_token()).collect::<Vec<_>>();
+
+        // from each token start, including those inside interpolations, and from the whitespace
+        // before `class` and before `b`
+        let class = source.find("class").unwrap();
+        let b = source.rfind('b').unwrap();
+        for offset in tokens
+            .iter()
+            .map(|token| token.start)
+            .chain([class - 2, b - 1])
+        {
+            scanner.rescan_from(offset);
+            let tail = std::iter::from_fn(|| scanner.scan_token()).collect::<Vec<_>>();
+
+            let expected = tokens.iter().skip_while(|token| token.start < offset);
+            assert!(tail.iter().eq(expected), "from {offset}");
+        }
+    }
+
     #[test]
     fn string_should_succeed() {
         let source = r#"var x = "string""#.to_string().into_bytes();
@@ -348,6 +622,104 @@ mod tests {
         assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 16, 1);
     }
 
+    #[test]
+    fn string_escape_should_succeed() {
+        let source = r#""a \"b\" \\" 1"#.to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_STRING,
+            r#""a \"b\" \\""#,
+            0,
+            1,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "1", 13, 1);
+    }
+
+    #[test]
+    fn interpolation_should_succeed() {
+        let source = r#""a ${ {1: 2}[1] } b ${c}""#.to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_INTERPOLATION,
+            r#""a ${"#,
+            0,
+            1,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_LEFT_BRACE, "{", 6, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "1", 7, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_COLON, ":", 8, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "2", 10, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_RIGHT_BRACE, "}", 11, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_LEFT_BRACKET, "[", 12, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "1", 13, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_RIGHT_BRACKET, "]", 14, 1);
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_INTERPOLATION,
+            "} b ${",
+            16,
+            1,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "c", 22, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_STRING, r#"}""#, 23, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 25, 1);
+    }
+
+    #[test]
+    fn peek_token_should_succeed() {
+        let source = r#""a ${b} c""#.to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_INTERPOLATION,
+            r#""a ${"#,
+            0,
+            1,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "b", 5, 1);
+        // the `}` closing the interpolation is still scanned as one after peeking past it
+        let peeked = scanner.peek_token().unwrap();
+        assert_eq!(peeked.token_type, TokenType::TOKEN_STRING);
+        assert_token(&mut scanner, TokenType::TOKEN_STRING, r#"} c""#, 6, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 10, 1);
+    }
+
+    #[test]
+    fn triple_quoted_string_should_succeed() {
+        let source = "\"\"\"one\ntwo \"quoted\"\nthree\"\"\";\nprint"
+            .to_string()
+            .into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_STRING,
+            "\"\"\"one\ntwo \"quoted\"\nthree\"\"\"",
+            0,
+            3,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_SEMICOLON, ";", 28, 3);
+        assert_token(&mut scanner, TokenType::TOKEN_PRINT, "print", 30, 4);
+
+        // two quotes are still an empty string
+        let mut scanner = Scanner::new(r#""" 1"#.to_string().into_bytes());
+        assert_token(&mut scanner, TokenType::TOKEN_STRING, r#""""#, 0, 1);
+    }
+
+    #[test]
+    fn triple_quoted_string_should_fail() {
+        let mut scanner = Scanner::new("\"\"\"one\ntwo\"\"".to_string().into_bytes());
+
+        let token = scanner.scan_token().unwrap();
+        assert_eq!(token.token_type, TokenType::TOKEN_ERROR);
+        assert_eq!(token.message, Some("Unterminated string."));
+    }
+
     #[test]
     fn boolean_should_succeed() {
         let source = "true".to_string().into_bytes();
@@ -386,4 +758,232 @@ mod tests {
 
         assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "3", 1, 2);
     }
+
+    #[test]
+    fn reset_should_succeed() {
+        let source = "1\n\n2".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "1", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "2", 3, 3);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 4, 3);
+
+        scanner.reset("\n3".to_string().into_bytes());
+
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "3", 1, 2);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 2, 2);
+    }
+
+    #[test]
+    fn identifier_should_succeed() {
+        let source = "var list_2 = [nil]".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_VAR, "var", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "list_2", 4, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EQUAL, "=", 11, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_LEFT_BRACKET, "[", 13, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NIL, "nil", 14, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_RIGHT_BRACKET, "]", 17, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 18, 1);
+    }
+
+    #[test]
+    fn is_should_succeed() {
+        let source = "x is A if island".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "x", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IS, "is", 2, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "A", 5, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IF, "if", 7, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "island", 10, 1);
+    }
+
+    #[test]
+    fn keyword_prefix_should_succeed() {
+        let source = "forest classy orchid returns fo retur"
+            .to_string()
+            .into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "forest", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "classy", 7, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "orchid", 14, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "returns", 21, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "fo", 29, 1);
+        // a keyword cut short by the end of the source
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "retur", 32, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 37, 1);
+    }
+
+    #[test]
+    fn dot_dot_should_succeed() {
+        let source = "0..10 a.b ...c".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "0", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_DOT_DOT, "..", 1, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "10", 3, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "a", 6, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_DOT, ".", 7, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "b", 8, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_ELLIPSIS, "...", 10, 1);
+    }
+
+    #[test]
+    fn slash_should_succeed() {
+        let source = "a / b // half\nc".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "a", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_SLASH, "/", 2, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "b", 4, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "c", 14, 2);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 15, 2);
+    }
+
+    #[test]
+    fn comments_should_succeed() {
+        let source = "// one\nvar a; /* two\nlines */ a /* three */;\n// four"
+            .to_string()
+            .into_bytes();
+        let mut scanner = Scanner::new(source).with_comments(true);
+
+        let line = TOKEN_COMMENT(CommentType::COMMENT_LINE);
+        let block = TOKEN_COMMENT(CommentType::COMMENT_BLOCK);
+        assert_token(&mut scanner, line, "// one", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_VAR, "var", 7, 2);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "a", 11, 2);
+        assert_token(&mut scanner, TokenType::TOKEN_SEMICOLON, ";", 12, 2);
+        assert_token(&mut scanner, block, "/* two\nlines */", 14, 3);
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "a", 30, 3);
+        assert_token(&mut scanner, block, "/* three */", 32, 3);
+        assert_token(&mut scanner, TokenType::TOKEN_SEMICOLON, ";", 43, 3);
+        assert_token(&mut scanner, line, "// four", 45, 4);
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 52, 4);
+    }
+
+    #[test]
+    fn comments_should_fail() {
+        let mut scanner = Scanner::new("a /* open".to_string().into_bytes());
+
+        assert_token(&mut scanner, TokenType::TOKEN_IDENTIFIER, "a", 0, 1);
+        let token = scanner.scan_token().unwrap();
+        assert_eq!(token.token_type, TokenType::TOKEN_ERROR);
+        assert_eq!(token.message, Some("Unterminated comment."));
+    }
+
+    #[test]
+    fn many_comments_should_succeed() {
+        let source = format!("{}a", "// c\n".repeat(100_000)).into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(
+            &mut scanner,
+            TokenType::TOKEN_IDENTIFIER,
+            "a",
+            500_000,
+            100_001,
+        );
+        assert_token(&mut scanner, TokenType::TOKEN_EOF, "", 500_001, 100_001);
+    }
+
+    #[test]
+    fn map_should_succeed() {
+        let source = r#"{"a": 1}"#.to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        assert_token(&mut scanner, TokenType::TOKEN_LEFT_BRACE, "{", 0, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_STRING, r#""a""#, 1, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_COLON, ":", 4, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_NUMBER, "1", 6, 1);
+        assert_token(&mut scanner, TokenType::TOKEN_RIGHT_BRACE, "}", 7, 1);
+    }
+
+    #[test]
+    fn column_should_succeed() {
+        let source = "var a;\n  print a;".to_string().into_bytes();
+        let mut scanner = Scanner::new(source);
+
+        let columns = std::iter::from_fn(|| scanner.scan_token())
+            .map(|token| (token.line, token.column))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            columns,
+            vec![(1, 1), (1, 5), (1, 6), (2, 3), (2, 9), (2, 10), (2, 11)]
+        );
+    }
+
+    #[test]
+    fn lexeme_should_succeed() {
+        let source = "var name = \"text\" + 12.5; @".to_string().into_bytes();
+        let mut scanner = Scanner::new(source.clone());
+
+        let lexemes = std::iter::from_fn(|| scanner.scan_token())
+            .map(|token| (token.token_type(), token.lexeme(&source).to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lexemes,
+            vec![
+                (TOKEN_VAR, "var".to_string()),
+                (TOKEN_IDENTIFIER, "name".to_string()),
+                (TOKEN_EQUAL, "=".to_string()),
+                (TOKEN_STRING, "\"text\"".to_string()),
+                (TOKEN_PLUS, "+".to_string()),
+                (TOKEN_NUMBER, "12.5".to_string()),
+                (TOKEN_SEMICOLON, ";".to_string()),
+                (TOKEN_ERROR, "Unexpected character.".to_string()),
+                (TOKEN_EOF, "".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn utf8_should_succeed() {
+        let source = "var café = \"crème 🎉\"; naïve".to_string().into_bytes();
+        let mut scanner = Scanner::new(source.clone());
+
+        let tokens = std::iter::from_fn(|| scanner.scan_token())
+            .map(|token| {
+                (
+                    token.token_type(),
+                    token.lexeme(&source).to_string(),
+                    token.column,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (TOKEN_VAR, "var".to_string(), 1),
+                (TOKEN_IDENTIFIER, "café".to_string(), 5),
+                (TOKEN_EQUAL, "=".to_string(), 10),
+                (TOKEN_STRING, "\"crème 🎉\"".to_string(), 12),
+                (TOKEN_SEMICOLON, ";".to_string(), 21),
+                (TOKEN_IDENTIFIER, "naïve".to_string(), 23),
+                (TOKEN_EOF, "".to_string(), 28),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_should_fail() {
+        let source = vec![b'a', 0xFF, b'"', b'b', 0xFE, b'"', b';'];
+        let mut scanner = Scanner::new(source.clone());
+
+        let tokens = std::iter::from_fn(|| scanner.scan_token())
+            .map(|token| (token.token_type(), token.lexeme(&source).to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tokens,
+            vec![
+                (TOKEN_IDENTIFIER, "a".to_string()),
+                (TOKEN_ERROR, "Invalid UTF-8.".to_string()),
+                (TOKEN_ERROR, "Invalid UTF-8.".to_string()),
+                (TOKEN_SEMICOLON, ";".to_string()),
+                (TOKEN_EOF, "".to_string()),
+            ]
+        );
+    }
 }
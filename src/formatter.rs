@@ -0,0 +1,271 @@
+use crate::compiler::scanner::Scanner;
+use crate::token::{CommentType, Token, TokenType, TokenType::*};
+
+const INDENT: &str = "  ";
+
+/// Re-emits `source` canonically: one statement per line, blocks indented by two spaces and single
+/// spaces around binary operators. Comments are kept, and so is one blank line where the source
+/// separates statements with any. Source that doesn't scan is returned as it is.
+pub fn format(source: &str) -> String {
+    let mut scanner = Scanner::new(source.as_bytes().to_vec()).with_comments(true);
+    let tokens = std::iter::from_fn(|| scanner.scan_token()).collect::<Vec<_>>();
+    if tokens.iter().any(|token| token.token_type == TOKEN_ERROR) {
+        return source.to_string();
+    }
+
+    let mut formatter = Formatter {
+        source: source.as_bytes(),
+        output: String::new(),
+        braces: vec![],
+        nesting: 0,
+        header: None,
+        previous: None,
+        unary: false,
+        after_block: false,
+        newline: false,
+    };
+    for token in tokens {
+        formatter.token(token);
+    }
+    formatter.output
+}
+
+#[derive(PartialEq)]
+enum Brace {
+    Block,
+    Map,
+}
+
+struct Formatter<'a> {
+    source: &'a [u8],
+    output: String,
+    /// every `{` still open, blocks are indented and maps stay on one line
+    braces: Vec<Brace>,
+    /// how many `(` and `[` are open, a `;` inside them doesn't end a statement
+    nesting: usize,
+    /// the nesting of a `for` or `class` header, whose `{` opens a block even after an operand
+    header: Option<usize>,
+    previous: Option<Token>,
+    /// the previous token was a prefix `-` or `!`
+    unary: bool,
+    /// the previous token closed a block
+    after_block: bool,
+    /// the next token starts a new line
+    newline: bool,
+}
+
+impl Formatter<'_> {
+    fn token(&mut self, token: Token) {
+        let token_type = token.token_type;
+        if token_type == TOKEN_EOF {
+            if !self.output.is_empty() {
+                self.output.push('\n');
+            }
+            return;
+        }
+
+        let text = token.lexeme(self.source);
+        let first_line = token.line - text.matches('\n').count();
+        let previous_line = self.previous.map(|previous| previous.line);
+        let is_comment = matches!(token_type, TOKEN_COMMENT(_));
+
+        let closes_block =
+            token_type == TOKEN_RIGHT_BRACE && self.braces.last() == Some(&Brace::Block);
+        let opens_block = token_type == TOKEN_LEFT_BRACE && self.opens_block();
+        if closes_block {
+            self.braces.pop();
+            // an empty block stays `{}`
+            self.newline = !self.previous_is(TOKEN_LEFT_BRACE);
+        }
+
+        // code after a comment on its own line starts a line of its own too
+        let after_comment = self.previous.is_some_and(|previous| {
+            matches!(previous.token_type, TOKEN_COMMENT(_)) && first_line > previous.line
+        });
+        // a comment on the same line as the code before it stays there
+        let trailing = is_comment && previous_line == Some(first_line);
+        // a block ending inside an expression or before `else` continues its line
+        let continues = self.after_block
+            && matches!(
+                token_type,
+                TOKEN_SEMICOLON | TOKEN_COMMA | TOKEN_RIGHT_PAREN | TOKEN_DOT | TOKEN_ELSE
+            );
+
+        if (self.newline || after_comment) && !trailing && !continues {
+            let blank = previous_line.is_some_and(|line| first_line > line + 1)
+                && !closes_block
+                && !self.previous_is(TOKEN_LEFT_BRACE);
+            self.output.push('\n');
+            if blank {
+                self.output.push('\n');
+            }
+            let depth = self.braces.iter().filter(|&brace| brace == &Brace::Block);
+            self.output.push_str(&INDENT.repeat(depth.count()));
+            self.newline = false;
+        } else if self.space_before(token_type, text) {
+            self.output.push(' ');
+        }
+        if !trailing {
+            self.newline = false;
+        }
+        self.output.push_str(text);
+
+        self.unary = match token_type {
+            TOKEN_BANG => true,
+            TOKEN_MINUS => !self
+                .previous
+                .is_some_and(|previous| self.ends_operand(previous)),
+            _ => false,
+        };
+        self.after_block = closes_block;
+        match token_type {
+            TOKEN_LEFT_BRACE if opens_block => {
+                self.braces.push(Brace::Block);
+                self.header = None;
+                self.newline = true;
+            }
+            TOKEN_LEFT_BRACE => self.braces.push(Brace::Map),
+            TOKEN_RIGHT_BRACE if closes_block => self.newline = true,
+            TOKEN_RIGHT_BRACE => {
+                self.braces.pop();
+            }
+            TOKEN_LEFT_PAREN | TOKEN_LEFT_BRACKET => self.nesting += 1,
+            TOKEN_RIGHT_PAREN | TOKEN_RIGHT_BRACKET => {
+                self.nesting = self.nesting.saturating_sub(1)
+            }
+            TOKEN_SEMICOLON if self.nesting == 0 => {
+                self.header = None;
+                self.newline = true;
+            }
+            TOKEN_FOR | TOKEN_CLASS => self.header = Some(self.nesting),
+            TOKEN_COMMENT(CommentType::COMMENT_LINE) => self.newline = true,
+            _ => {}
+        }
+        self.previous = Some(token);
+    }
+
+    fn previous_is(&self, token_type: TokenType) -> bool {
+        self.previous
+            .is_some_and(|previous| previous.token_type == token_type)
+    }
+
+    /// Tells a `{` opening a block from one opening a map literal.
+    fn opens_block(&self) -> bool {
+        let Some(previous) = self.previous else {
+            return true;
+        };
+
+        self.newline
+            || matches!(previous.token_type, TOKEN_RIGHT_PAREN | TOKEN_ELSE)
+            || (self.header == Some(self.nesting) && self.ends_operand(previous))
+    }
+
+    /// Whether `token` can end an operand, which makes a following `-` binary and a following `(`
+    /// or `[` a call or an index.
+    fn ends_operand(&self, token: Token) -> bool {
+        match token.token_type {
+            TOKEN_IDENTIFIER | TOKEN_NUMBER | TOKEN_STRING | TOKEN_TRUE | TOKEN_FALSE
+            | TOKEN_NIL | TOKEN_THIS | TOKEN_SUPER | TOKEN_RIGHT_PAREN | TOKEN_RIGHT_BRACKET => {
+                true
+            }
+            TOKEN_RIGHT_BRACE => !self.after_block,
+            _ => false,
+        }
+    }
+
+    fn space_before(&self, token_type: TokenType, text: &str) -> bool {
+        let Some(previous) = self.previous else {
+            return false;
+        };
+        if self.unary
+            || matches!(
+                previous.token_type,
+                TOKEN_LEFT_PAREN
+                    | TOKEN_LEFT_BRACKET
+                    | TOKEN_LEFT_BRACE
+                    | TOKEN_DOT
+                    | TOKEN_DOT_DOT
+                    | TOKEN_ELLIPSIS
+                    | TOKEN_INTERPOLATION
+            )
+        {
+            return false;
+        }
+
+        match token_type {
+            TOKEN_SEMICOLON | TOKEN_COMMA | TOKEN_COLON | TOKEN_DOT | TOKEN_DOT_DOT
+            | TOKEN_RIGHT_PAREN | TOKEN_RIGHT_BRACKET | TOKEN_RIGHT_BRACE => false,
+            TOKEN_LEFT_PAREN => !self.ends_operand(previous) && previous.token_type != TOKEN_FUN,
+            TOKEN_LEFT_BRACKET => !self.ends_operand(previous),
+            // the rest of an interpolated string after the expression
+            TOKEN_STRING | TOKEN_INTERPOLATION => !text.starts_with('}'),
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::tests::Output;
+    use crate::vm::VM;
+
+    const PROGRAMS: [&str; 4] = [
+        "var a=1;var b = [1,2 ,3];\nprint a+b[0]*-2;",
+        "// counts down\nfun count(n){while(n>0){print n;n=n-1;}return n;}\n\n\ncount(3); // done",
+        "class A{init(x){this.x=x;}get(){return this.x;}}\nclass B<A{get(){return super.get()+1;}}\nprint B(1).get();",
+        "var m={\"a\":{\"b\":1}};\n/* block\ncomment */\nfor x in [1 ,2]{print \"${x} and ${m[\"a\"]}\";}\nfor i in 0..2 print i;\nvar f=(a,...rest)=>a+rest[0];print f(1,2);",
+    ];
+
+    fn run(source: &str) -> String {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        vm.interpret(source.to_string().into_bytes()).unwrap();
+        output.contents()
+    }
+
+    #[test]
+    fn format_should_succeed() {
+        assert_eq!(
+            format(PROGRAMS[1]),
+            "// counts down\n\
+             fun count(n) {\n\
+             \x20 while (n > 0) {\n\
+             \x20   print n;\n\
+             \x20   n = n - 1;\n\
+             \x20 }\n\
+             \x20 return n;\n\
+             }\n\
+             \n\
+             count(3); // done\n"
+        );
+        assert_eq!(
+            format(PROGRAMS[3]),
+            "var m = {\"a\": {\"b\": 1}};\n\
+             /* block\ncomment */\n\
+             for x in [1, 2] {\n\
+             \x20 print \"${x} and ${m[\"a\"]}\";\n\
+             }\n\
+             for i in 0..2 print i;\n\
+             var f = (a, ...rest) => a + rest[0];\n\
+             print f(1, 2);\n"
+        );
+        assert_eq!(format("class A {}"), "class A {}\n");
+        assert_eq!(format(""), "");
+    }
+
+    #[test]
+    fn format_idempotent_should_succeed() {
+        for program in PROGRAMS {
+            let formatted = format(program);
+            assert_eq!(format(&formatted), formatted, "{program}");
+            assert_eq!(run(&formatted), run(program), "{program}");
+        }
+    }
+
+    #[test]
+    fn format_should_fail() {
+        let source = "var a = \"unterminated;";
+        assert_eq!(format(source), source);
+    }
+}
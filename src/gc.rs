@@ -0,0 +1,219 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
+use crate::object::{Class, Closure, Instance, ObjectType, Upvalue};
+use crate::value::Value;
+
+/// Number of tracked objects before the first collection.
+const INITIAL_NEXT_GC: usize = 1024;
+/// The threshold grows to this multiple of the objects surviving a collection.
+const GC_GROW_FACTOR: usize = 2;
+
+/// Objects are reference counted, so only cycles leak. Every cycle passes through an object
+/// with interior mutability, so those are the ones tracked here. Sweeping empties the
+/// unreachable ones, which breaks their cycles and lets the reference counts free them.
+enum HeapObject {
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<HashMap<String, Value>>>),
+    Instance(Weak<RefCell<Instance>>),
+    Class(Weak<Class>),
+    Upvalue(Weak<RefCell<Upvalue>>),
+}
+
+pub(crate) struct Heap {
+    objects: Vec<HeapObject>,
+    allocated_since_gc: usize,
+    next_gc: usize,
+    /// collect at every safe point after an allocation, set with `RLOX_GC_STRESS=1`
+    pub(crate) stress: bool,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            objects: vec![],
+            allocated_since_gc: 0,
+            next_gc: INITIAL_NEXT_GC,
+            stress: std::env::var("RLOX_GC_STRESS").is_ok_and(|value| value == "1"),
+        }
+    }
+
+    /// Number of tracked objects that were alive at the last collection or allocated since.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn track(&mut self, object_type: &ObjectType) {
+        let object = match object_type {
+            ObjectType::OBJ_LIST(list) => HeapObject::List(Rc::downgrade(list)),
+            ObjectType::OBJ_MAP(map) => HeapObject::Map(Rc::downgrade(map)),
+            ObjectType::OBJ_INSTANCE(instance) => HeapObject::Instance(Rc::downgrade(instance)),
+            ObjectType::OBJ_CLASS(class) => HeapObject::Class(Rc::downgrade(class)),
+            // immutable objects only point at objects older than themselves
+            _ => return,
+        };
+        self.objects.push(object);
+        self.allocated_since_gc += 1;
+    }
+
+    pub fn track_upvalue(&mut self, upvalue: &Rc<RefCell<Upvalue>>) {
+        self.objects
+            .push(HeapObject::Upvalue(Rc::downgrade(upvalue)));
+        self.allocated_since_gc += 1;
+    }
+
+    pub fn should_collect(&self) -> bool {
+        (self.stress && self.allocated_since_gc > 0) || self.objects.len() > self.next_gc
+    }
+
+    pub fn collect<'a>(
+        &mut self,
+        values: impl IntoIterator<Item = &'a Value>,
+        upvalues: &[Rc<RefCell<Upvalue>>],
+        closures: impl IntoIterator<Item = &'a Rc<Closure>>,
+    ) {
+        let mut marker = Marker::default();
+        marker
+            .gray
+            .extend(values.into_iter().cloned().map(Gray::Value));
+        marker
+            .gray
+            .extend(upvalues.iter().cloned().map(Gray::Upvalue));
+        marker
+            .gray
+            .extend(closures.into_iter().cloned().map(Gray::Closure));
+        marker.trace();
+
+        self.sweep(&marker.marked);
+        self.allocated_since_gc = 0;
+        self.next_gc = INITIAL_NEXT_GC.max(self.objects.len() * GC_GROW_FACTOR);
+    }
+
+    fn sweep(&mut self, marked: &HashSet<usize>) {
+        self.objects.retain(|object| match object {
+            HeapObject::List(list) => retain(list, marked, |list| list.borrow_mut().clear()),
+            HeapObject::Map(map) => retain(map, marked, |map| map.borrow_mut().clear()),
+            HeapObject::Instance(instance) => retain(instance, marked, |instance| {
+                instance.borrow_mut().fields.clear()
+            }),
+            HeapObject::Class(class) => retain(class, marked, |class| {
+                class.methods.borrow_mut().clear();
+                class.superclass.borrow_mut().take();
+            }),
+            HeapObject::Upvalue(upvalue) => retain(upvalue, marked, |upvalue| {
+                *upvalue.borrow_mut() = Upvalue::Closed(Value::VAL_NIL)
+            }),
+        });
+    }
+}
+
+/// Keeps a reachable object tracked, empties an unreachable one and forgets about it.
+fn retain<T>(object: &Weak<T>, marked: &HashSet<usize>, clear: impl FnOnce(&T)) -> bool {
+    let Some(object) = object.upgrade() else {
+        return false;
+    };
+    if marked.contains(&address(&object)) {
+        return true;
+    }
+
+    clear(&object);
+    false
+}
+
+fn address<T>(object: &Rc<T>) -> usize {
+    Rc::as_ptr(object) as *const () as usize
+}
+
+enum Gray {
+    Value(Value),
+    Closure(Rc<Closure>),
+    Upvalue(Rc<RefCell<Upvalue>>),
+    Class(Rc<Class>),
+}
+
+#[derive(Default)]
+struct Marker {
+    marked: HashSet<usize>,
+    /// reached but not yet traced, kept as a worklist so deep object graphs don't recurse
+    gray: Vec<Gray>,
+}
+
+impl Marker {
+    /// Returns `true` the first time an object is seen.
+    fn mark<T>(&mut self, object: &Rc<T>) -> bool {
+        self.marked.insert(address(object))
+    }
+
+    fn trace(&mut self) {
+        while let Some(gray) = self.gray.pop() {
+            match gray {
+                Gray::Value(value) => self.blacken_value(&value),
+                Gray::Closure(closure) => {
+                    if self.mark(&closure) {
+                        self.gray
+                            .extend(closure.upvalues.iter().cloned().map(Gray::Upvalue));
+                        self.gray
+                            .extend(closure.superclass.clone().map(Gray::Class));
+                    }
+                }
+                Gray::Upvalue(upvalue) => {
+                    if self.mark(&upvalue) {
+                        if let Upvalue::Closed(value) = &*upvalue.borrow() {
+                            self.gray.push(Gray::Value(value.clone()));
+                        }
+                    }
+                }
+                Gray::Class(class) => {
+                    if self.mark(&class) {
+                        self.gray
+                            .extend(class.methods.borrow().values().cloned().map(Gray::Closure));
+                        self.gray
+                            .extend(class.superclass.borrow().clone().map(Gray::Class));
+                    }
+                }
+            }
+        }
+    }
+
+    fn blacken_value(&mut self, value: &Value) {
+        let Value::VAL_OBJECT(object) = value else {
+            return;
+        };
+
+        match &object.object_type {
+            ObjectType::OBJ_STRING(_)
+            | ObjectType::OBJ_FUNCTION(_)
+            | ObjectType::OBJ_NATIVE(_)
+            | ObjectType::OBJ_RANGE(_) => {}
+            ObjectType::OBJ_LIST(list) => {
+                if self.mark(list) {
+                    self.gray
+                        .extend(list.borrow().iter().cloned().map(Gray::Value));
+                }
+            }
+            ObjectType::OBJ_MAP(map) => {
+                if self.mark(map) {
+                    self.gray
+                        .extend(map.borrow().values().cloned().map(Gray::Value));
+                }
+            }
+            ObjectType::OBJ_INSTANCE(instance) => {
+                if self.mark(instance) {
+                    let instance = instance.borrow();
+                    self.gray.push(Gray::Class(instance.class.clone()));
+                    self.gray
+                        .extend(instance.fields.values().cloned().map(Gray::Value));
+                }
+            }
+            ObjectType::OBJ_CLASS(class) => self.gray.push(Gray::Class(class.clone())),
+            ObjectType::OBJ_CLOSURE(closure) => self.gray.push(Gray::Closure(closure.clone())),
+            ObjectType::OBJ_BOUND_METHOD(bound) => {
+                if self.mark(bound) {
+                    self.gray.push(Gray::Value(bound.receiver.clone()));
+                    self.gray.push(Gray::Closure(bound.method.clone()));
+                }
+            }
+        }
+    }
+}
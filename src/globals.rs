@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+
+use crate::value::Value;
+
+/// Global variables, each kept in a slot that never moves once its name is first defined. That
+/// lets an instruction remember the slot it resolved and skip the name lookup from then on.
+#[derive(Default)]
+pub(crate) struct Globals {
+    slots: HashMap<String, usize>,
+    /// `None` for a global that was removed, its slot stays reserved for the name
+    values: Vec<Option<Value>>,
+}
+
+impl Globals {
+    /// Slot of the global called `name`, `None` if it was never defined.
+    pub fn slot(&self, name: &str) -> Option<usize> {
+        self.slots.get(name).copied()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.slot(name).and_then(|slot| self.get_slot(slot))
+    }
+
+    pub fn get_slot(&self, slot: usize) -> Option<&Value> {
+        self.values.get(slot)?.as_ref()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Defines or redefines `name`. A redefinition overwrites the same slot, so slots that were
+    /// already resolved see the new value.
+    pub fn insert(&mut self, name: &str, value: Value) {
+        match self.slot(name) {
+            Some(slot) => self.values[slot] = Some(value),
+            None => {
+                self.slots.insert(name.to_string(), self.values.len());
+                self.values.push(Some(value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        if let Some(slot) = self.slot(name) {
+            self.values[slot] = None;
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Value> {
+        self.values.iter().flatten()
+    }
+}
@@ -7,7 +7,13 @@ pub mod op;
 pub mod op_code;
 
 pub mod compiler;
+pub mod formatter;
+pub mod gc;
+pub mod globals;
+pub mod native;
 pub mod object;
 pub mod token;
 pub mod value;
 pub mod vm;
+
+pub use formatter::format;
@@ -1,65 +1,199 @@
 #![allow(unused)]
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, Read, Write};
 use std::process::exit;
 
 use anyhow::Result;
 
 use rlox::chunk::Chunk;
+use rlox::compiler::scanner::Scanner;
 use rlox::compiler::Compiler;
-use rlox::op_code::OpCode::{OP_ADD, OP_CONSTANT, OP_DIVIDE, OP_NEGATE, OP_RETURN};
+use rlox::token::TokenType;
 use rlox::vm::{InterpretError, VM};
 
 fn main() {
-    let args = std::env::args();
-    let argc = args.len();
-    if argc == 1 {
-        repl();
-    } else if argc == 2 {
-        let code = args.collect::<Vec<_>>()[1].clone();
-        run_file(code);
-    } else {
-        eprintln!("Usage: rlox: [path]");
-        exit(64);
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.as_slice() {
+        [] => {
+            repl();
+            Ok(())
+        }
+        [flag, rest @ ..] if flag == "--dump-tokens" && rest.len() <= 1 => {
+            read_source(rest.first()).map(dump_tokens)
+        }
+        [flag, rest @ ..] if flag == "--dump-bytecode" && rest.len() <= 1 => {
+            read_source(rest.first()).and_then(dump_bytecode)
+        }
+        [flag, rest @ ..] if flag == "--check" && rest.len() <= 1 => {
+            read_source(rest.first()).map(|source| check(source, rest.first()))
+        }
+        [flag, source] if flag == "-e" || flag == "--eval" => {
+            run(VM::new(), source.clone().into_bytes());
+            Ok(())
+        }
+        [path] if !path.starts_with('-') => run_file(path.clone()),
+        _ => {
+            eprintln!("Usage: rlox [--dump-tokens | --dump-bytecode | --check] [path]");
+            eprintln!("       rlox -e | --eval <source>");
+            exit(64);
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e}");
+        exit(74);
     }
 }
 
 fn repl() {
+    let mut vm = VM::new();
     let mut buffer = String::new();
     loop {
-        std::io::stdout().write_all("> ".as_bytes()).unwrap();
+        let prompt = if buffer.is_empty() { "> " } else { ". " };
+        std::io::stdout().write_all(prompt.as_bytes()).unwrap();
         std::io::stdout().flush().unwrap();
 
-        let _ = std::io::stdin().lock().read_line(&mut buffer).unwrap();
-        buffer = buffer.trim().to_string();
-        interpret(buffer.clone().into_bytes()).unwrap();
+        let mut line = String::new();
+        if std::io::stdin().lock().read_line(&mut line).unwrap() == 0 {
+            if !buffer.trim().is_empty() {
+                let _ = interpret(&mut vm, buffer.into_bytes());
+            }
+            println!();
+            return;
+        }
+
+        // keep reading until every opened delimiter is closed, a blank line forces evaluation
+        let blank = line.trim().is_empty();
+        buffer.push_str(&line);
+        if !blank && delimiter_depth(&buffer) > 0 {
+            continue;
+        }
 
+        if !buffer.trim().is_empty() {
+            let _ = interpret(&mut vm, buffer.trim().to_string().into_bytes());
+        }
         buffer.clear();
     }
 }
 
-fn interpret(source: Vec<u8>) -> Result<(), InterpretError> {
-    let mut chunk = Chunk::default();
-    let mut compiler = Compiler::new(&mut chunk);
-    compiler.compile(source);
-    Ok(())
+/// How many `(`, `[` and `{` are still open in `source`. Counted from its tokens, so delimiters
+/// inside strings, interpolations and comments are left to the scanner.
+fn delimiter_depth(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut scanner = Scanner::new(source.as_bytes().to_vec());
+    while let Some(token) = scanner.scan_token() {
+        match token.token_type() {
+            TokenType::TOKEN_LEFT_PAREN
+            | TokenType::TOKEN_LEFT_BRACKET
+            | TokenType::TOKEN_LEFT_BRACE => depth += 1,
+            TokenType::TOKEN_RIGHT_PAREN
+            | TokenType::TOKEN_RIGHT_BRACKET
+            | TokenType::TOKEN_RIGHT_BRACE => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn interpret(vm: &mut VM, source: Vec<u8>) -> Result<(), InterpretError> {
+    vm.interpret(source)
+        .map_err(|e| match e.downcast::<InterpretError>() {
+            Ok(e) => e,
+            Err(e) => InterpretError::RUNTIME_ERROR {
+                message: e.to_string(),
+                line: 0,
+            },
+        })
 }
 
 fn run_file(path: String) -> Result<()> {
-    let source = read_file(path)?;
+    let source = read_file(path.clone())?;
+    run(VM::new().with_source_name(path), source);
+    Ok(())
+}
 
-    match interpret(source) {
+/// Runs the source, exiting with 65 on a compile error or 70 on a runtime error.
+fn run(mut vm: VM, source: Vec<u8>) {
+    match interpret(&mut vm, source) {
         Ok(_) => {}
         Err(e) => match e {
-            InterpretError::COMPILE_ERROR => exit(65),
-            InterpretError::RUNTIME_ERROR => exit(70),
+            InterpretError::COMPILE_ERROR { .. } => exit(65),
+            InterpretError::RUNTIME_ERROR { .. } => exit(70),
         },
     }
+}
+
+/// Prints every token the scanner produces, one per line, then exits.
+fn dump_tokens(source: Vec<u8>) {
+    let mut scanner = Scanner::new(source);
+    let mut had_error = false;
+    while let Some(token) = scanner.scan_token() {
+        had_error |= token.token_type() == TokenType::TOKEN_ERROR;
+        println!("{}", token.describe(scanner.source()));
+    }
 
+    if had_error {
+        exit(65);
+    }
+}
+
+/// Compiles the source and prints the disassembled chunk without running it.
+fn dump_bytecode(source: Vec<u8>) -> Result<()> {
+    let mut chunk = Chunk::default();
+    if !Compiler::new(&mut chunk).compile(source) {
+        exit(65);
+    }
+
+    print!("{}", chunk.disassemble_to_string("script")?);
     Ok(())
 }
 
+/// Compiles the source without running it, reporting every error and exiting with 65 if there
+/// were any.
+fn check(source: Vec<u8>, path: Option<&String>) {
+    let mut chunk = Chunk::default();
+    let mut compiler = Compiler::new(&mut chunk);
+    if let Some(path) = path {
+        compiler.set_source_name(path.as_str());
+    }
+
+    if !compiler.compile(source) {
+        exit(65);
+    }
+}
+
+/// Reads the file at `path`, or all of stdin when no path was given.
+fn read_source(path: Option<&String>) -> Result<Vec<u8>> {
+    match path {
+        Some(path) => read_file(path.clone()),
+        None => {
+            let mut source = vec![];
+            std::io::stdin().read_to_end(&mut source)?;
+            Ok(source)
+        }
+    }
+}
+
 fn read_file(path: String) -> Result<Vec<u8>> {
     let file = std::fs::read(path)?;
     Ok(file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delimiter_depth_should_succeed() {
+        assert_eq!(delimiter_depth("while (true) {"), 1);
+        assert_eq!(delimiter_depth("var a = [1, {\"b\": 2}];"), 0);
+        assert_eq!(delimiter_depth("print \"{\";"), 0);
+        assert_eq!(delimiter_depth("print \"\\\"{\";"), 0);
+        assert_eq!(delimiter_depth("}"), -1);
+        assert_eq!(delimiter_depth("print 1; // {"), 0);
+        assert_eq!(delimiter_depth("/* ( */ print [1,"), 1);
+        assert_eq!(delimiter_depth("print \"${ \"x\" }\";"), 0);
+        assert_eq!(delimiter_depth("print \"${ {\"a\": \"{\"} }\" + ("), 1);
+        assert_eq!(delimiter_depth("print \"\"\"a \" { \"\"\";"), 0);
+    }
+}
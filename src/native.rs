@@ -0,0 +1,205 @@
+use anyhow::{anyhow, Result};
+
+use crate::object::{NativeFn, Object, ObjectType};
+use crate::value::Value;
+use crate::vm::VM;
+
+/// The math library, left out of a VM built `with_stdlib(false)`.
+const MATH: [(&str, usize, NativeFn); 9] = [
+    ("abs", 1, abs),
+    ("min", 2, min),
+    ("max", 2, max),
+    ("floor", 1, floor),
+    ("ceil", 1, ceil),
+    ("sqrt", 1, sqrt),
+    ("sin", 1, sin),
+    ("cos", 1, cos),
+    ("pow", 2, pow),
+];
+const MATH_CONSTANTS: [(&str, f32); 2] = [("PI", std::f32::consts::PI), ("E", std::f32::consts::E)];
+
+/// Registers the built-in functions every VM starts with.
+pub(crate) fn define_natives(vm: &mut VM) {
+    vm.define_native("type", 1, type_);
+    vm.define_native("len", 1, len);
+    vm.define_native("substr", 3, substr);
+    vm.define_native("number", 1, number);
+    vm.define_native("read_line", 0, read_line);
+    vm.define_native("write", 1, write);
+    define_math(vm);
+}
+
+pub(crate) fn define_math(vm: &mut VM) {
+    for (name, arity, function) in MATH {
+        vm.define_native(name, arity, function);
+    }
+    for (name, value) in MATH_CONSTANTS {
+        vm.define_global(name, Value::VAL_NUMBER(value));
+    }
+}
+
+pub(crate) fn remove_math(vm: &mut VM) {
+    let names = MATH.iter().map(|(name, ..)| name);
+    for name in names.chain(MATH_CONSTANTS.iter().map(|(name, _)| name)) {
+        vm.remove_global(name);
+    }
+}
+
+fn string(string: String) -> Value {
+    Value::VAL_OBJECT(Object {
+        object_type: ObjectType::OBJ_STRING(string.into()),
+    })
+}
+
+fn as_string(value: &Value) -> Result<&str> {
+    value
+        .as_string()
+        .ok_or_else(|| anyhow!("Expected a string but got a {}.", value.type_name()))
+}
+
+fn as_number(value: &Value) -> Result<f32> {
+    value
+        .as_number()
+        .ok_or_else(|| anyhow!("Expected a number but got a {}.", value.type_name()))
+}
+
+fn as_count(value: &Value) -> Result<usize> {
+    match value {
+        Value::VAL_NUMBER(n) if *n >= 0. && n.fract() == 0. => Ok(*n as usize),
+        value => Err(anyhow!(
+            "Expected a non-negative whole number but got {value}."
+        )),
+    }
+}
+
+/// `type(value)` is the name of the value's type, like `"number"` or `"string"`.
+fn type_(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(string(args[0].type_name().to_string()))
+}
+
+/// `len(value)` counts the characters of a string or the items of a list or map.
+fn len(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    let len = match &args[0] {
+        Value::VAL_OBJECT(Object { object_type }) => match object_type {
+            ObjectType::OBJ_STRING(string) => string.chars().count(),
+            ObjectType::OBJ_LIST(list) => list.borrow().len(),
+            ObjectType::OBJ_MAP(map) => map.borrow().len(),
+            _ => {
+                return Err(anyhow!(
+                    "Can't take the length of a {}.",
+                    args[0].type_name()
+                ))
+            }
+        },
+        value => return Err(anyhow!("Can't take the length of a {}.", value.type_name())),
+    };
+    Ok(Value::VAL_NUMBER(len as f32))
+}
+
+/// `substr(string, start, length)` takes up to `length` characters from `start`, stopping at the
+/// end of the string. Positions count characters, so multibyte ones are never split.
+fn substr(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    let source = as_string(&args[0])?;
+    let start = as_count(&args[1])?;
+    let length = as_count(&args[2])?;
+
+    let chars = source.chars().count();
+    if start > chars {
+        return Err(anyhow!(
+            "Start {start} is past the end of a string of length {chars}."
+        ));
+    }
+    Ok(string(source.chars().skip(start).take(length).collect()))
+}
+
+/// `number(string)` parses a decimal or `0x` hexadecimal number, surrounding whitespace is
+/// ignored. Anything else is `nil` so scripts can check the result.
+fn number(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    let text = as_string(&args[0])?.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+
+    let parsed = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|n| n as f32),
+        None if digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') => {
+            digits.parse::<f32>().ok()
+        }
+        None => None,
+    };
+
+    Ok(match parsed {
+        Some(n) if negative => Value::VAL_NUMBER(-n),
+        Some(n) => Value::VAL_NUMBER(n),
+        None => Value::VAL_NIL,
+    })
+}
+
+/// `read_line()` is the next line of input without its line ending, or `nil` at the end.
+fn read_line(vm: &mut VM, _args: &[Value]) -> Result<Value> {
+    Ok(match vm.read_line()? {
+        Some(line) => string(line),
+        None => Value::VAL_NIL,
+    })
+}
+
+/// `write(value)` prints `value` like `print` does but without a newline after it.
+fn write(vm: &mut VM, args: &[Value]) -> Result<Value> {
+    vm.write(&args[0].to_string())?;
+    Ok(Value::VAL_NIL)
+}
+
+/// `abs(n)` is `n` without its sign.
+fn abs(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.abs()))
+}
+
+/// `min(a, b)` is the smaller of two numbers.
+fn min(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(
+        as_number(&args[0])?.min(as_number(&args[1])?),
+    ))
+}
+
+/// `max(a, b)` is the larger of two numbers.
+fn max(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(
+        as_number(&args[0])?.max(as_number(&args[1])?),
+    ))
+}
+
+/// `floor(n)` rounds `n` down to a whole number.
+fn floor(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.floor()))
+}
+
+/// `ceil(n)` rounds `n` up to a whole number.
+fn ceil(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.ceil()))
+}
+
+/// `sqrt(n)` is the square root of `n`, NaN when `n` is negative.
+fn sqrt(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.sqrt()))
+}
+
+/// `sin(n)` is the sine of `n` radians.
+fn sin(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.sin()))
+}
+
+/// `cos(n)` is the cosine of `n` radians.
+fn cos(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(as_number(&args[0])?.cos()))
+}
+
+/// `pow(base, exp)` raises `base` to the power `exp`.
+fn pow(_vm: &mut VM, args: &[Value]) -> Result<Value> {
+    Ok(Value::VAL_NUMBER(
+        as_number(&args[0])?.powf(as_number(&args[1])?),
+    ))
+}
@@ -1,28 +1,347 @@
 use std::cell::{Ref, RefCell};
-use std::fmt::{Display, Formatter};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::rc::Rc;
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+use anyhow::Result;
+
+use crate::chunk::Chunk;
+use crate::value::Value;
+use crate::vm::VM;
+
+#[derive(Debug, PartialEq, Clone)]
 pub struct Object {
     pub(crate) object_type: ObjectType,
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, Clone)]
 pub enum ObjectType {
-    OBJ_STRING(String),
+    /// shared, so copying a string value doesn't copy its characters
+    OBJ_STRING(Rc<str>),
+    /// shared so that every variable holding the list sees writes through an index
+    OBJ_LIST(Rc<RefCell<Vec<Value>>>),
+    /// keys are strings, number keys are stored by their display form so `1` and `"1"` are the
+    /// same key, and `-0` is stored as `0` since the two are equal
+    OBJ_MAP(Rc<RefCell<HashMap<String, Value>>>),
+    OBJ_CLASS(Rc<Class>),
+    OBJ_INSTANCE(Rc<RefCell<Instance>>),
+    OBJ_FUNCTION(Rc<Function>),
+    OBJ_CLOSURE(Rc<Closure>),
+    OBJ_BOUND_METHOD(Rc<BoundMethod>),
+    OBJ_NATIVE(Rc<Native>),
+    OBJ_RANGE(Rc<Range>),
+}
+
+impl ObjectType {
+    /// Name of the type as seen by scripts, returned by `type()`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ObjectType::OBJ_STRING(_) => "string",
+            ObjectType::OBJ_LIST(_) => "list",
+            ObjectType::OBJ_MAP(_) => "map",
+            ObjectType::OBJ_CLASS(_) => "class",
+            ObjectType::OBJ_INSTANCE(_) => "instance",
+            ObjectType::OBJ_FUNCTION(_)
+            | ObjectType::OBJ_CLOSURE(_)
+            | ObjectType::OBJ_BOUND_METHOD(_)
+            | ObjectType::OBJ_NATIVE(_) => "function",
+            ObjectType::OBJ_RANGE(_) => "range",
+        }
+    }
+}
+
+pub struct Function {
+    /// number of parameters, including those with a default
+    pub(crate) arity: usize,
+    /// where the initializer of each trailing parameter with a default starts. A call missing
+    /// arguments starts at the first missing one's and runs the rest of them before the body.
+    pub(crate) defaults: Vec<usize>,
+    /// where the body starts, past the default initializers
+    pub(crate) body: usize,
+    /// the last parameter collects any further arguments into a list, its default is `[]`
+    pub(crate) variadic: bool,
+    pub(crate) upvalue_count: usize,
+    pub(crate) chunk: Chunk,
+    /// empty for the top-level script and anonymous functions
+    pub(crate) name: String,
+}
+
+impl Function {
+    /// Fewest arguments a call can pass, the parameters without a default.
+    pub fn min_arity(&self) -> usize {
+        self.arity - self.defaults.len()
+    }
+
+    /// Where a call passing `arg_count` arguments starts executing.
+    pub(crate) fn entry(&self, arg_count: usize) -> usize {
+        match arg_count.checked_sub(self.min_arity()) {
+            Some(given) if given < self.defaults.len() => self.defaults[given],
+            _ => self.body,
+        }
+    }
+}
+
+impl Debug for Function {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // the script and anonymous functions have no name
+        if self.name.is_empty() {
+            write!(f, "<fn>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+pub type NativeFn = fn(&mut VM, &[Value]) -> Result<Value>;
+
+/// A built-in function implemented in Rust, errors it returns become runtime errors.
+pub struct Native {
+    pub(crate) name: &'static str,
+    pub(crate) arity: usize,
+    pub(crate) function: NativeFn,
+}
+
+impl Debug for Native {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+/// `start..end`, the numbers from `start` towards `end`, leaving `end` out.
+#[derive(Debug, PartialEq)]
+pub struct Range {
+    pub(crate) start: f32,
+    pub(crate) end: f32,
+    /// 1, or -1 when the range counts down
+    pub(crate) step: f32,
+}
+
+impl Range {
+    pub fn new(start: f32, end: f32) -> Self {
+        let step = if end < start { -1. } else { 1. };
+        Self { start, end, step }
+    }
+
+    /// The number `index` steps from the start, `None` once that reaches the end.
+    pub fn get(&self, index: usize) -> Option<f32> {
+        let number = self.start + index as f32 * self.step;
+        let before_end = if self.step > 0. {
+            number < self.end
+        } else {
+            number > self.end
+        };
+        before_end.then_some(number)
+    }
+}
+
+/// A function together with the variables it captured from enclosing functions.
+#[derive(Debug, Clone)]
+pub struct Closure {
+    pub(crate) function: Rc<Function>,
+    pub(crate) upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// what `super` refers to inside the function: the superclass of the class declaring
+    /// a method, or whatever the enclosing function saw for functions nested in one
+    pub(crate) superclass: Option<Rc<Class>>,
+}
+
+/// A captured variable, living on the stack until its scope ends and moved into the upvalue after.
+#[derive(Debug)]
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// A method looked up on an instance, remembering the instance to use as `this`.
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub(crate) receiver: Value,
+    pub(crate) method: Rc<Closure>,
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub(crate) name: String,
+    pub(crate) methods: RefCell<HashMap<String, Rc<Closure>>>,
+    pub(crate) superclass: RefCell<Option<Rc<Class>>>,
+}
+
+impl Class {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            methods: RefCell::new(HashMap::new()),
+            superclass: RefCell::new(None),
+        }
+    }
+}
+
+pub struct Instance {
+    pub(crate) class: Rc<Class>,
+    pub(crate) fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+// fields can point back at the instance, so only the class name is printed
+impl Debug for Instance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
+
+/// Strings and collections compare by contents, everything else by identity. Lists and maps are
+/// equal when their items are, recursively, even though they're mutable and a later write can
+/// make them differ. Lengths are compared before any item, and a collection always equals
+/// itself without looking inside. Collections that contain themselves are equal when every
+/// path through them meets equal items.
+impl PartialEq for ObjectType {
+    fn eq(&self, other: &Self) -> bool {
+        objects_equal(self, other, &mut vec![])
+    }
+}
+
+/// `comparing` holds the addresses of the collection pairs whose items are being compared.
+/// Reaching one of them again means both sides loop back the same way, which doesn't make them
+/// differ, so the pair counts as equal there instead of recursing forever.
+fn objects_equal(a: &ObjectType, b: &ObjectType, comparing: &mut Vec<(usize, usize)>) -> bool {
+    match (a, b) {
+        (ObjectType::OBJ_STRING(a), ObjectType::OBJ_STRING(b)) => Rc::ptr_eq(a, b) || a == b,
+        (ObjectType::OBJ_LIST(a), ObjectType::OBJ_LIST(b)) => {
+            collections_equal(a, b, comparing, |a, b, comparing| {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|(a, b)| values_equal(a, b, comparing))
+            })
+        }
+        (ObjectType::OBJ_MAP(a), ObjectType::OBJ_MAP(b)) => {
+            collections_equal(a, b, comparing, |a, b, comparing| {
+                a.len() == b.len()
+                    && a.iter()
+                        .all(|(key, a)| b.get(key).is_some_and(|b| values_equal(a, b, comparing)))
+            })
+        }
+        (ObjectType::OBJ_CLASS(a), ObjectType::OBJ_CLASS(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_INSTANCE(a), ObjectType::OBJ_INSTANCE(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_FUNCTION(a), ObjectType::OBJ_FUNCTION(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_CLOSURE(a), ObjectType::OBJ_CLOSURE(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_BOUND_METHOD(a), ObjectType::OBJ_BOUND_METHOD(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_NATIVE(a), ObjectType::OBJ_NATIVE(b)) => Rc::ptr_eq(a, b),
+        (ObjectType::OBJ_RANGE(a), ObjectType::OBJ_RANGE(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn collections_equal<T>(
+    a: &Rc<RefCell<T>>,
+    b: &Rc<RefCell<T>>,
+    comparing: &mut Vec<(usize, usize)>,
+    items_equal: impl FnOnce(&T, &T, &mut Vec<(usize, usize)>) -> bool,
+) -> bool {
+    let pair = (Rc::as_ptr(a) as usize, Rc::as_ptr(b) as usize);
+    if Rc::ptr_eq(a, b) || comparing.contains(&pair) {
+        return true;
+    }
+
+    comparing.push(pair);
+    let equal = items_equal(&a.borrow(), &b.borrow(), comparing);
+    comparing.pop();
+    equal
+}
+
+fn values_equal(a: &Value, b: &Value, comparing: &mut Vec<(usize, usize)>) -> bool {
+    match (a, b) {
+        (Value::VAL_OBJECT(a), Value::VAL_OBJECT(b)) => {
+            objects_equal(&a.object_type, &b.object_type, comparing)
+        }
+        (a, b) => a == b,
+    }
 }
 
 impl Display for Object {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Object { object_type } => match object_type {
-                ObjectType::OBJ_STRING(s) => writeln!(f, "{}", s),
-            },
+        write_object(&self.object_type, f, &mut vec![])
+    }
+}
+
+/// `printing` holds the addresses of the collections whose items are being written, one that
+/// holds itself is written as `[...]` or `{...}` where it's reached again instead of recursing
+/// forever.
+fn write_object(
+    object_type: &ObjectType,
+    f: &mut Formatter<'_>,
+    printing: &mut Vec<usize>,
+) -> std::fmt::Result {
+    match object_type {
+        ObjectType::OBJ_STRING(s) => write!(f, "{}", s),
+        ObjectType::OBJ_LIST(list) => {
+            let address = Rc::as_ptr(list) as usize;
+            if printing.contains(&address) {
+                return write!(f, "[...]");
+            }
+
+            printing.push(address);
+            write!(f, "[")?;
+            for (i, item) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_value(item, f, printing)?;
+            }
+            printing.pop();
+            write!(f, "]")
+        }
+        ObjectType::OBJ_MAP(map) => {
+            let address = Rc::as_ptr(map) as usize;
+            if printing.contains(&address) {
+                return write!(f, "{{...}}");
+            }
+
+            printing.push(address);
+            let map = map.borrow();
+            let mut keys = map.keys().collect::<Vec<_>>();
+            keys.sort();
+            write!(f, "{{")?;
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{key}: ")?;
+                write_value(&map[key], f, printing)?;
+            }
+            printing.pop();
+            write!(f, "}}")
         }
+        ObjectType::OBJ_CLASS(class) => write!(f, "{}", class.name),
+        ObjectType::OBJ_INSTANCE(instance) => write!(f, "{:?}", instance.borrow()),
+        ObjectType::OBJ_FUNCTION(function) => write!(f, "{function:?}"),
+        ObjectType::OBJ_CLOSURE(closure) => write!(f, "{:?}", closure.function),
+        ObjectType::OBJ_BOUND_METHOD(bound) => write!(f, "{:?}", bound.method.function),
+        ObjectType::OBJ_NATIVE(native) => write!(f, "{native:?}"),
+        ObjectType::OBJ_RANGE(range) => write!(
+            f,
+            "{}..{}",
+            Value::VAL_NUMBER(range.start),
+            Value::VAL_NUMBER(range.end)
+        ),
     }
 }
 
-impl From<Object> for u8 {
-    fn from(value: Object) -> Self {
-        0 // TODO
+fn write_value(
+    value: &Value,
+    f: &mut Formatter<'_>,
+    printing: &mut Vec<usize>,
+) -> std::fmt::Result {
+    match value {
+        Value::VAL_OBJECT(object) => write_object(&object.object_type, f, printing),
+        value => write!(f, "{value}"),
     }
 }
@@ -1,6 +1,8 @@
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::fmt::{Display, Formatter};
 
-#[derive(Debug, PartialEq, IntoPrimitive, TryFromPrimitive)]
+use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
+
+#[derive(Debug, Clone, Copy, PartialEq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum OpCode {
     OP_CONSTANT,
@@ -17,4 +19,294 @@ pub enum OpCode {
     OP_LESS,
     OP_NOT,
     OP_NIL,
+    OP_POP,
+    OP_ASSERT,
+    OP_JUMP_IF_FALSE,
+    OP_LOOP,
+    OP_PRINT,
+    OP_DEFINE_GLOBAL,
+    OP_GET_GLOBAL,
+    OP_SET_GLOBAL,
+    OP_BUILD_LIST,
+    OP_INDEX_GET,
+    OP_INDEX_SET,
+    OP_BUILD_MAP,
+    OP_CLASS,
+    OP_CALL,
+    OP_GET_PROPERTY,
+    OP_SET_PROPERTY,
+    OP_GET_LOCAL,
+    OP_SET_LOCAL,
+    OP_METHOD,
+    OP_INVOKE,
+    OP_INHERIT,
+    OP_GET_SUPER,
+    OP_SUPER_INVOKE,
+    OP_CLOSURE,
+    OP_GET_UPVALUE,
+    OP_SET_UPVALUE,
+    OP_CLOSE_UPVALUE,
+    OP_JUMP,
+    OP_DEFINE_CONST_GLOBAL,
+    OP_POPN,
+    OP_TO_STRING,
+    OP_IS,
+    OP_RANGE,
+    OP_ITER_NEXT,
+    OP_TAIL_CALL,
+    OP_TAIL_INVOKE,
+}
+
+/// Every byte below this is an opcode, keep it one past the last variant.
+const OPCODE_COUNT: u8 = OpCode::OP_TAIL_INVOKE as u8 + 1;
+
+impl OpCode {
+    /// Decodes an instruction byte with a single range check, which is cheaper than `try_from`'s
+    /// match over every variant in the VM's dispatch loop. Fails the same way `try_from` does.
+    #[inline(always)]
+    pub fn decode(byte: u8) -> Result<OpCode, TryFromPrimitiveError<OpCode>> {
+        if byte >= OPCODE_COUNT {
+            return Err(TryFromPrimitiveError { number: byte });
+        }
+
+        // SAFETY: `OpCode` is `repr(u8)` with the discriminants `0..OPCODE_COUNT`
+        let op_code = unsafe { std::mem::transmute::<u8, OpCode>(byte) };
+        debug_assert_eq!(OpCode::try_from(byte).ok(), Some(op_code));
+        Ok(op_code)
+    }
+
+    /// Looks an opcode up by its `Debug` name like `OP_ADD`, or its `Display` name like `add`.
+    pub fn from_name(name: &str) -> Option<OpCode> {
+        (0..OPCODE_COUNT)
+            .filter_map(|byte| OpCode::decode(byte).ok())
+            .find(|op_code| format!("{op_code:?}") == name || op_code.to_string() == name)
+    }
+
+    /// Number of operand bytes following the opcode. `OP_CLOSURE` is also followed by an
+    /// `is_local`, `index` byte pair per upvalue of its function, which this leaves out.
+    pub fn operand_len(&self) -> usize {
+        match self {
+            OpCode::OP_CONSTANT
+            | OpCode::OP_PRINT
+            | OpCode::OP_DEFINE_GLOBAL
+            | OpCode::OP_DEFINE_CONST_GLOBAL
+            | OpCode::OP_GET_GLOBAL
+            | OpCode::OP_SET_GLOBAL
+            | OpCode::OP_BUILD_LIST
+            | OpCode::OP_BUILD_MAP
+            | OpCode::OP_CLASS
+            | OpCode::OP_CALL
+            | OpCode::OP_TAIL_CALL
+            | OpCode::OP_GET_PROPERTY
+            | OpCode::OP_SET_PROPERTY
+            | OpCode::OP_GET_LOCAL
+            | OpCode::OP_SET_LOCAL
+            | OpCode::OP_METHOD
+            | OpCode::OP_GET_SUPER
+            | OpCode::OP_CLOSURE
+            | OpCode::OP_GET_UPVALUE
+            | OpCode::OP_SET_UPVALUE
+            | OpCode::OP_POPN => 1,
+            OpCode::OP_JUMP_IF_FALSE
+            | OpCode::OP_LOOP
+            | OpCode::OP_JUMP
+            | OpCode::OP_ITER_NEXT
+            | OpCode::OP_INVOKE
+            | OpCode::OP_TAIL_INVOKE
+            | OpCode::OP_SUPER_INVOKE => 2,
+            OpCode::OP_ADD
+            | OpCode::OP_SUBTRACT
+            | OpCode::OP_MULTIPLY
+            | OpCode::OP_DIVIDE
+            | OpCode::OP_NEGATE
+            | OpCode::OP_RETURN
+            | OpCode::OP_TRUE
+            | OpCode::OP_FALSE
+            | OpCode::OP_EQUAL
+            | OpCode::OP_GREATER
+            | OpCode::OP_LESS
+            | OpCode::OP_NOT
+            | OpCode::OP_NIL
+            | OpCode::OP_POP
+            | OpCode::OP_ASSERT
+            | OpCode::OP_INDEX_GET
+            | OpCode::OP_INDEX_SET
+            | OpCode::OP_INHERIT
+            | OpCode::OP_CLOSE_UPVALUE
+            | OpCode::OP_TO_STRING
+            | OpCode::OP_IS
+            | OpCode::OP_RANGE => 0,
+        }
+    }
+}
+
+/// The name without its `OP_` prefix in lowercase, like `add`, for disassembly shown to users.
+/// `Debug` keeps the full `OP_ADD` form.
+impl Display for OpCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = format!("{self:?}");
+        f.pad(&name["OP_".len()..].to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_should_succeed() {
+        // a variant appended without moving OPCODE_COUNT would disagree here
+        for byte in 0..=u8::MAX {
+            assert_eq!(OpCode::decode(byte).ok(), OpCode::try_from(byte).ok());
+        }
+    }
+
+    #[test]
+    fn operand_len_should_succeed() {
+        let expected = [
+            (OpCode::OP_CONSTANT, 1),
+            (OpCode::OP_ADD, 0),
+            (OpCode::OP_SUBTRACT, 0),
+            (OpCode::OP_MULTIPLY, 0),
+            (OpCode::OP_DIVIDE, 0),
+            (OpCode::OP_NEGATE, 0),
+            (OpCode::OP_RETURN, 0),
+            (OpCode::OP_TRUE, 0),
+            (OpCode::OP_FALSE, 0),
+            (OpCode::OP_EQUAL, 0),
+            (OpCode::OP_GREATER, 0),
+            (OpCode::OP_LESS, 0),
+            (OpCode::OP_NOT, 0),
+            (OpCode::OP_NIL, 0),
+            (OpCode::OP_POP, 0),
+            (OpCode::OP_ASSERT, 0),
+            (OpCode::OP_JUMP_IF_FALSE, 2),
+            (OpCode::OP_LOOP, 2),
+            (OpCode::OP_PRINT, 1),
+            (OpCode::OP_DEFINE_GLOBAL, 1),
+            (OpCode::OP_GET_GLOBAL, 1),
+            (OpCode::OP_SET_GLOBAL, 1),
+            (OpCode::OP_BUILD_LIST, 1),
+            (OpCode::OP_INDEX_GET, 0),
+            (OpCode::OP_INDEX_SET, 0),
+            (OpCode::OP_BUILD_MAP, 1),
+            (OpCode::OP_CLASS, 1),
+            (OpCode::OP_CALL, 1),
+            (OpCode::OP_GET_PROPERTY, 1),
+            (OpCode::OP_SET_PROPERTY, 1),
+            (OpCode::OP_GET_LOCAL, 1),
+            (OpCode::OP_SET_LOCAL, 1),
+            (OpCode::OP_METHOD, 1),
+            (OpCode::OP_INVOKE, 2),
+            (OpCode::OP_INHERIT, 0),
+            (OpCode::OP_GET_SUPER, 1),
+            (OpCode::OP_SUPER_INVOKE, 2),
+            (OpCode::OP_CLOSURE, 1),
+            (OpCode::OP_GET_UPVALUE, 1),
+            (OpCode::OP_SET_UPVALUE, 1),
+            (OpCode::OP_CLOSE_UPVALUE, 0),
+            (OpCode::OP_JUMP, 2),
+            (OpCode::OP_DEFINE_CONST_GLOBAL, 1),
+            (OpCode::OP_POPN, 1),
+            (OpCode::OP_TO_STRING, 0),
+            (OpCode::OP_IS, 0),
+            (OpCode::OP_RANGE, 0),
+            (OpCode::OP_ITER_NEXT, 2),
+            (OpCode::OP_TAIL_CALL, 1),
+            (OpCode::OP_TAIL_INVOKE, 2),
+        ];
+
+        // listed in discriminant order, so a new opcode has to be added here too
+        assert_eq!(expected.len(), OPCODE_COUNT as usize);
+        for (byte, (op_code, operand_len)) in expected.iter().enumerate() {
+            assert_eq!(OpCode::decode(byte as u8).unwrap(), *op_code);
+            assert_eq!(op_code.operand_len(), *operand_len, "{op_code:?}");
+        }
+    }
+
+    #[test]
+    fn display_should_succeed() {
+        let expected = [
+            (OpCode::OP_CONSTANT, "constant"),
+            (OpCode::OP_ADD, "add"),
+            (OpCode::OP_SUBTRACT, "subtract"),
+            (OpCode::OP_MULTIPLY, "multiply"),
+            (OpCode::OP_DIVIDE, "divide"),
+            (OpCode::OP_NEGATE, "negate"),
+            (OpCode::OP_RETURN, "return"),
+            (OpCode::OP_TRUE, "true"),
+            (OpCode::OP_FALSE, "false"),
+            (OpCode::OP_EQUAL, "equal"),
+            (OpCode::OP_GREATER, "greater"),
+            (OpCode::OP_LESS, "less"),
+            (OpCode::OP_NOT, "not"),
+            (OpCode::OP_NIL, "nil"),
+            (OpCode::OP_POP, "pop"),
+            (OpCode::OP_ASSERT, "assert"),
+            (OpCode::OP_JUMP_IF_FALSE, "jump_if_false"),
+            (OpCode::OP_LOOP, "loop"),
+            (OpCode::OP_PRINT, "print"),
+            (OpCode::OP_DEFINE_GLOBAL, "define_global"),
+            (OpCode::OP_GET_GLOBAL, "get_global"),
+            (OpCode::OP_SET_GLOBAL, "set_global"),
+            (OpCode::OP_BUILD_LIST, "build_list"),
+            (OpCode::OP_INDEX_GET, "index_get"),
+            (OpCode::OP_INDEX_SET, "index_set"),
+            (OpCode::OP_BUILD_MAP, "build_map"),
+            (OpCode::OP_CLASS, "class"),
+            (OpCode::OP_CALL, "call"),
+            (OpCode::OP_GET_PROPERTY, "get_property"),
+            (OpCode::OP_SET_PROPERTY, "set_property"),
+            (OpCode::OP_GET_LOCAL, "get_local"),
+            (OpCode::OP_SET_LOCAL, "set_local"),
+            (OpCode::OP_METHOD, "method"),
+            (OpCode::OP_INVOKE, "invoke"),
+            (OpCode::OP_INHERIT, "inherit"),
+            (OpCode::OP_GET_SUPER, "get_super"),
+            (OpCode::OP_SUPER_INVOKE, "super_invoke"),
+            (OpCode::OP_CLOSURE, "closure"),
+            (OpCode::OP_GET_UPVALUE, "get_upvalue"),
+            (OpCode::OP_SET_UPVALUE, "set_upvalue"),
+            (OpCode::OP_CLOSE_UPVALUE, "close_upvalue"),
+            (OpCode::OP_JUMP, "jump"),
+            (OpCode::OP_DEFINE_CONST_GLOBAL, "define_const_global"),
+            (OpCode::OP_POPN, "popn"),
+            (OpCode::OP_TO_STRING, "to_string"),
+            (OpCode::OP_IS, "is"),
+            (OpCode::OP_RANGE, "range"),
+            (OpCode::OP_ITER_NEXT, "iter_next"),
+            (OpCode::OP_TAIL_CALL, "tail_call"),
+            (OpCode::OP_TAIL_INVOKE, "tail_invoke"),
+        ];
+
+        assert_eq!(expected.len(), OPCODE_COUNT as usize);
+        for (op_code, name) in expected {
+            assert_eq!(op_code.to_string(), name);
+        }
+        assert_eq!(format!("{:<8}|", OpCode::OP_ADD), "add     |");
+        assert_eq!(format!("{:?}", OpCode::OP_ADD), "OP_ADD");
+    }
+
+    #[test]
+    fn from_name_should_succeed() {
+        assert_eq!(OpCode::from_name("OP_ADD"), Some(OpCode::OP_ADD));
+        assert_eq!(OpCode::from_name("add"), Some(OpCode::OP_ADD));
+        assert_eq!(
+            OpCode::from_name("jump_if_false"),
+            Some(OpCode::OP_JUMP_IF_FALSE)
+        );
+        for byte in 0..OPCODE_COUNT {
+            let op_code = OpCode::decode(byte).unwrap();
+            assert_eq!(OpCode::from_name(&format!("{op_code:?}")), Some(op_code));
+            assert_eq!(OpCode::from_name(&op_code.to_string()), Some(op_code));
+        }
+    }
+
+    #[test]
+    fn from_name_should_fail() {
+        assert_eq!(OpCode::from_name("OP_NOPE"), None);
+        assert_eq!(OpCode::from_name("ADD"), None);
+        assert_eq!(OpCode::from_name("op_add"), None);
+        assert_eq!(OpCode::from_name(""), None);
+    }
 }
@@ -1,29 +1,89 @@
-#[derive(Debug, PartialEq, Clone)]
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Token {
     pub(crate) token_type: TokenType,
-    pub(crate) message: String,
+    /// text of tokens that aren't a slice of the source, like error messages
+    pub(crate) message: Option<&'static str>,
     pub(crate) start: usize,
+    pub(crate) length: usize,
     pub(crate) line: usize,
+    pub(crate) column: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, message: String, start: usize, line: usize) -> Self {
+    pub fn new(
+        token_type: TokenType,
+        start: usize,
+        length: usize,
+        line: usize,
+        column: usize,
+    ) -> Self {
         Self {
             token_type,
-            message,
+            message: None,
             start,
+            length,
             line,
+            column,
+        }
+    }
+
+    /// A token that doesn't come from the source, `text` is its lexeme.
+    pub fn synthetic(token_type: TokenType, text: &'static str) -> Self {
+        Self {
+            token_type,
+            message: Some(text),
+            start: 0,
+            length: text.len(),
+            line: 0,
+            column: 0,
         }
     }
+
+    pub fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+
+    /// Byte range of the token in the source it was scanned from. Error tokens are empty ranges at
+    /// the error, synthetic tokens aren't from any source and start at 0.
+    pub fn span(&self) -> Range<usize> {
+        self.start..self.start + self.length
+    }
+
+    /// The token's text, borrowed from the `source` it was scanned from.
+    pub fn lexeme<'a>(&self, source: &'a [u8]) -> &'a str {
+        match self.message {
+            Some(message) => message,
+            None => source
+                .get(self.start..self.start + self.length)
+                .and_then(|lexeme| std::str::from_utf8(lexeme).ok())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Renders the token as `line:column type "lexeme"`, as printed by `--dump-tokens`.
+    pub fn describe(&self, source: &[u8]) -> String {
+        format!(
+            "{:4}:{:<4} {:?} {:?}",
+            self.line,
+            self.column,
+            self.token_type,
+            self.lexeme(source)
+        )
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TokenType {
     // Single-character tokens.
     TOKEN_LEFT_PAREN,
     TOKEN_RIGHT_PAREN,
     TOKEN_LEFT_BRACE,
     TOKEN_RIGHT_BRACE,
+    TOKEN_LEFT_BRACKET,
+    TOKEN_RIGHT_BRACKET,
+    TOKEN_COLON,
     TOKEN_COMMA,
     TOKEN_DOT,
     TOKEN_MINUS,
@@ -40,18 +100,29 @@ pub enum TokenType {
     TOKEN_GREATER_EQUAL,
     TOKEN_LESS,
     TOKEN_LESS_EQUAL,
+    TOKEN_ARROW,
+    TOKEN_ELLIPSIS,
+    TOKEN_DOT_DOT,
     // Literals.
     TOKEN_IDENTIFIER,
     TOKEN_STRING,
+    /// the start of a string up to a `${`, or its continuation between a `}` and the next `${`
+    TOKEN_INTERPOLATION,
     TOKEN_NUMBER,
     // Keywords.
     TOKEN_AND,
+    TOKEN_ASSERT,
+    TOKEN_BREAK,
     TOKEN_CLASS,
+    TOKEN_CONST,
+    TOKEN_CONTINUE,
     TOKEN_ELSE,
     TOKEN_FALSE,
     TOKEN_FOR,
     TOKEN_FUN,
     TOKEN_IF,
+    TOKEN_IN,
+    TOKEN_IS,
     TOKEN_NIL,
     TOKEN_OR,
     TOKEN_PRINT,
@@ -62,6 +133,82 @@ pub enum TokenType {
     TOKEN_VAR,
     TOKEN_WHILE,
 
+    /// only scanned when the scanner keeps comments, for tooling like formatters
+    TOKEN_COMMENT(CommentType),
     TOKEN_ERROR,
     TOKEN_EOF,
 }
+
+impl TokenType {
+    /// The keyword spelled `text`, `None` if `text` is any other identifier.
+    pub fn keyword_from_str(text: &str) -> Option<TokenType> {
+        KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == text)
+            .map(|&(_, token_type)| token_type)
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        KEYWORDS.iter().any(|(_, token_type)| token_type == self)
+    }
+}
+
+/// Every reserved word and the token it scans as.
+pub const KEYWORDS: [(&str, TokenType); 22] = [
+    ("and", TokenType::TOKEN_AND),
+    ("assert", TokenType::TOKEN_ASSERT),
+    ("break", TokenType::TOKEN_BREAK),
+    ("class", TokenType::TOKEN_CLASS),
+    ("const", TokenType::TOKEN_CONST),
+    ("continue", TokenType::TOKEN_CONTINUE),
+    ("else", TokenType::TOKEN_ELSE),
+    ("false", TokenType::TOKEN_FALSE),
+    ("for", TokenType::TOKEN_FOR),
+    ("fun", TokenType::TOKEN_FUN),
+    ("if", TokenType::TOKEN_IF),
+    ("in", TokenType::TOKEN_IN),
+    ("is", TokenType::TOKEN_IS),
+    ("nil", TokenType::TOKEN_NIL),
+    ("or", TokenType::TOKEN_OR),
+    ("print", TokenType::TOKEN_PRINT),
+    ("return", TokenType::TOKEN_RETURN),
+    ("super", TokenType::TOKEN_SUPER),
+    ("this", TokenType::TOKEN_THIS),
+    ("true", TokenType::TOKEN_TRUE),
+    ("var", TokenType::TOKEN_VAR),
+    ("while", TokenType::TOKEN_WHILE),
+];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CommentType {
+    /// `//` up to the end of the line
+    COMMENT_LINE,
+    /// `/*` up to the next `*/`, which can span lines
+    COMMENT_BLOCK,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyword_should_succeed() {
+        assert_eq!(
+            TokenType::keyword_from_str("for"),
+            Some(TokenType::TOKEN_FOR)
+        );
+        for (keyword, token_type) in KEYWORDS {
+            assert_eq!(TokenType::keyword_from_str(keyword), Some(token_type));
+            assert!(token_type.is_keyword());
+        }
+    }
+
+    #[test]
+    fn keyword_should_fail() {
+        assert_eq!(TokenType::keyword_from_str("forx"), None);
+        assert_eq!(TokenType::keyword_from_str("fo"), None);
+        assert_eq!(TokenType::keyword_from_str(""), None);
+        assert!(!TokenType::TOKEN_IDENTIFIER.is_keyword());
+        assert!(!TokenType::TOKEN_PLUS.is_keyword());
+    }
+}
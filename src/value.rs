@@ -1,24 +1,13 @@
 use std::convert::Infallible;
 use std::fmt::{Display, Formatter};
 use std::num::ParseFloatError;
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::ops::{Add, Div, Index, Mul, Neg, Sub};
 use std::str::FromStr;
 use std::string::ParseError;
 
 use anyhow::{anyhow, Result};
 
-use crate::object::Object;
-
-impl From<Value> for Option<u8> {
-    fn from(val: Value) -> Self {
-        match val {
-            Value::VAL_BOOL(b) => Some(b as u8),
-            Value::VAL_NIL => None,
-            Value::VAL_NUMBER(n) => Some(n as u8),
-            Value::VAL_OBJECT(o) => Some(o.into()),
-        }
-    }
-}
+use crate::object::{Object, ObjectType};
 
 impl Add for Value {
     type Output = Result<f32>;
@@ -95,38 +84,330 @@ impl FromStr for Value {
     }
 }
 
-#[derive(Debug, PartialEq, PartialOrd, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     VAL_BOOL(bool),
     VAL_NIL,
     VAL_NUMBER(f32),
     VAL_OBJECT(Object),
+    /// how far a `for` loop is into what it iterates, only ever held by the loop's hidden local
+    /// so it counts past where an `f32` stops holding every integer
+    VAL_INDEX(usize),
+}
+
+impl Value {
+    /// Name of the value's type as seen by scripts, returned by `type()`.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::VAL_BOOL(_) => "bool",
+            Value::VAL_NIL => "nil",
+            Value::VAL_NUMBER(_) => "number",
+            Value::VAL_OBJECT(object) => object.object_type.type_name(),
+            Value::VAL_INDEX(_) => "index",
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f32> {
+        match self {
+            Value::VAL_NUMBER(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::VAL_BOOL(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&str> {
+        match self {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(string),
+            }) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// Only `nil` and `false` are falsey, every other value including `0` and `""` is truthy.
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::VAL_NIL | Value::VAL_BOOL(false))
+    }
+
+    pub fn is_truthy(&self) -> bool {
+        !self.is_falsey()
+    }
+
+    /// Converts the value to a byte when it has an exact byte representation:
+    /// booleans become `0`/`1` and whole numbers in `0..=255` keep their value.
+    /// `nil`, objects and any other number have none.
+    pub fn as_byte(&self) -> Option<u8> {
+        match self {
+            Value::VAL_BOOL(b) => Some(*b as u8),
+            Value::VAL_NUMBER(n) if n.fract() == 0. && (0. ..=255.).contains(n) => Some(*n as u8),
+            Value::VAL_NUMBER(_) | Value::VAL_NIL | Value::VAL_OBJECT(_) | Value::VAL_INDEX(_) => {
+                None
+            }
+        }
+    }
+}
+
+/// Significant digits a fractional number is printed with. An `f32` only holds about 7, the
+/// shortest round-tripping form can show a few more that are noise, like `0.33333334`.
+const NUMBER_PRECISION: i32 = 7;
+
+/// Whole numbers print without a decimal point, up to where they'd be a long run of digits.
+fn format_number(n: f32) -> String {
+    if !n.is_finite() || n == 0. {
+        return n.to_string();
+    }
+    if n.fract() == 0. && n.abs() < 1e15 {
+        return format!("{n}");
+    }
+
+    let magnitude = n.abs().log10().floor() as i32;
+    if !(-7..15).contains(&magnitude) {
+        return format!("{n:e}");
+    }
+
+    let decimals = (NUMBER_PRECISION - 1 - magnitude).max(0) as usize;
+    let formatted = format!("{n:.decimals$}");
+    if formatted.contains('.') {
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    } else {
+        formatted
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
-            Value::VAL_BOOL(b) => writeln!(f, "{b}"),
-            Value::VAL_NIL => writeln!(f, "nil"),
-            Value::VAL_NUMBER(n) => writeln!(f, "{n}"),
-            Value::VAL_OBJECT(o) => writeln!(f, "{o}"),
+            Value::VAL_BOOL(b) => write!(f, "{b}"),
+            Value::VAL_NIL => write!(f, "nil"),
+            Value::VAL_NUMBER(n) => write!(f, "{}", format_number(*n)),
+            Value::VAL_OBJECT(o) => write!(f, "{o}"),
+            Value::VAL_INDEX(index) => write!(f, "{index}"),
         }
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Default, PartialEq)]
 pub struct ValueArray {
-    pub count: usize,
     pub(crate) values: Vec<Value>,
 }
 
 impl ValueArray {
     pub fn write(&mut self, value: Value) {
-        if self.values.len() < self.count + 1 {
-            self.values.push(value);
-        } else {
-            self.values[self.count] = value;
+        self.values.push(value);
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Value> {
+        self.values.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Value> {
+        self.values.iter()
+    }
+}
+
+/// Panics when `index` is out of range, like a slice. Use `get` for untrusted indices.
+impl Index<usize> for ValueArray {
+    type Output = Value;
+
+    fn index(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn as_byte_should_succeed() {
+        assert_eq!(Value::VAL_BOOL(true).as_byte(), Some(1));
+        assert_eq!(Value::VAL_BOOL(false).as_byte(), Some(0));
+        assert_eq!(Value::VAL_NUMBER(0.).as_byte(), Some(0));
+        assert_eq!(Value::VAL_NUMBER(255.).as_byte(), Some(255));
+
+        assert_eq!(Value::VAL_NUMBER(256.).as_byte(), None);
+        assert_eq!(Value::VAL_NUMBER(-1.).as_byte(), None);
+        assert_eq!(Value::VAL_NUMBER(1.5).as_byte(), None);
+        assert_eq!(Value::VAL_NIL.as_byte(), None);
+
+        let string = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("a".into()),
+        });
+        assert_eq!(string.as_byte(), None);
+    }
+
+    #[test]
+    fn accessors_should_succeed() {
+        let string = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("a".into()),
+        });
+
+        assert_eq!(Value::VAL_NUMBER(1.5).as_number(), Some(1.5));
+        assert_eq!(Value::VAL_BOOL(false).as_bool(), Some(false));
+        assert_eq!(string.as_string(), Some("a"));
+    }
+
+    #[test]
+    fn accessors_should_fail() {
+        let string = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("1".into()),
+        });
+        let list = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_LIST(Rc::new(RefCell::new(vec![]))),
+        });
+
+        for value in [
+            Value::VAL_NIL,
+            Value::VAL_BOOL(true),
+            string.clone(),
+            list.clone(),
+        ] {
+            assert_eq!(value.as_number(), None, "{value}");
+        }
+        for value in [Value::VAL_NIL, Value::VAL_NUMBER(0.), string, list.clone()] {
+            assert_eq!(value.as_bool(), None, "{value}");
+        }
+        for value in [
+            Value::VAL_NIL,
+            Value::VAL_BOOL(true),
+            Value::VAL_NUMBER(1.),
+            list,
+        ] {
+            assert_eq!(value.as_string(), None, "{value}");
         }
-        self.count += 1;
+    }
+
+    #[test]
+    fn is_falsey_should_succeed() {
+        assert!(Value::VAL_NIL.is_falsey());
+        assert!(Value::VAL_BOOL(false).is_falsey());
+
+        assert!(!Value::VAL_NIL.is_truthy());
+        assert!(!Value::VAL_BOOL(false).is_truthy());
+    }
+
+    #[test]
+    fn is_falsey_should_fail() {
+        let empty = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("".into()),
+        });
+        for value in [
+            Value::VAL_BOOL(true),
+            Value::VAL_NUMBER(0.),
+            Value::VAL_NUMBER(f32::NAN),
+            empty,
+        ] {
+            assert!(!value.is_falsey(), "{value}");
+            assert!(value.is_truthy(), "{value}");
+        }
+    }
+
+    #[test]
+    fn string_clone_should_succeed() {
+        let string = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("shared".into()),
+        });
+        let copy = string.clone();
+
+        // both values point at the same characters
+        let (
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(a),
+            }),
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(b),
+            }),
+        ) = (&string, &copy)
+        else {
+            unreachable!()
+        };
+        assert!(Rc::ptr_eq(a, b));
+        assert_eq!(string, copy);
+    }
+
+    #[test]
+    fn string_equality_should_succeed() {
+        // separately allocated strings still compare by contents
+        let a = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("ab".into()),
+        });
+        let b = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING(format!("{}{}", "a", "b").into()),
+        });
+        let c = Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("ba".into()),
+        });
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn display_number_should_succeed() {
+        for (number, expected) in [
+            (10. / 2., "5"),
+            (10. / 3., "3.333333"),
+            (-0., "-0"),
+            (0.1 + 0.2, "0.3"),
+            (1.1 * 1.1, "1.21"),
+            (-2.5, "-2.5"),
+            (123456., "123456"),
+            (1e20, "1e20"),
+            (1e-9, "1e-9"),
+            (f32::INFINITY, "inf"),
+        ] {
+            assert_eq!(Value::VAL_NUMBER(number).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn value_array_write_should_succeed() {
+        const N: usize = 8;
+        let mut array = ValueArray::default();
+        for n in 0..=N {
+            array.write(Value::VAL_NUMBER(n as f32));
+        }
+
+        assert_eq!(array.len(), N + 1);
+        assert_eq!(array[N], Value::VAL_NUMBER(N as f32));
+    }
+
+    #[test]
+    fn value_array_iter_should_succeed() {
+        let mut array = ValueArray::default();
+        assert!(array.is_empty());
+        assert_eq!(array.iter().next(), None);
+
+        for n in 0..3 {
+            array.write(Value::VAL_NUMBER(n as f32));
+        }
+
+        let numbers = array
+            .iter()
+            .map(|value| value.as_number().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(numbers, [0., 1., 2.]);
+        assert_eq!(array.get(2), Some(&Value::VAL_NUMBER(2.)));
+        assert_eq!(array.get(3), None);
     }
 }
@@ -1,76 +1,394 @@
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, Ref, RefCell};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Error;
+use std::io::{BufRead, Write};
 use std::ptr::eq;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use anyhow::{anyhow, Result};
 use num_enum::TryFromPrimitiveError;
 use thiserror::Error;
 
 use crate::chunk::{Chunk, Instruction};
-use crate::compiler::Compiler;
-use crate::object::{Object, ObjectType};
+use crate::compiler::{CompileError, Compiler};
+use crate::gc::Heap;
+use crate::globals::Globals;
+use crate::native;
+use crate::object::{
+    BoundMethod, Class, Closure, Function, Instance, Native, NativeFn, Object, ObjectType, Range,
+    Upvalue,
+};
 use crate::op::BinaryOp;
 use crate::op_code::OpCode;
 use crate::value::Value;
 use crate::value::Value::{VAL_BOOL, VAL_OBJECT};
-use crate::vm::InterpretError::COMPILE_ERROR;
+use crate::vm::InterpretError::{COMPILE_ERROR, RUNTIME_ERROR};
 
-const MAX_STACK_SIZE: usize = 256;
+const FRAMES_MAX: usize = 64;
+/// Default limit on the values on the stack, enough for every frame to use all 256 local slots.
+const MAX_STACK_SIZE: usize = FRAMES_MAX * 256;
+/// Instructions executed between two checks of the clock against the timeout.
+const TIMEOUT_CHECK_INTERVAL: u64 = 1024;
 
-pub struct VM {
-    chunk: Chunk,
+/// A function call in progress.
+struct CallFrame {
+    closure: Rc<Closure>,
     ip: usize,
-    stack: [Option<Value>; MAX_STACK_SIZE],
+    /// stack index of the frame's slot 0, the callee or `this`
+    slots: usize,
+}
+
+pub struct VM {
+    frames: Vec<CallFrame>,
+    /// grows as values are pushed, slots at and above `sp` are always empty
+    stack: Vec<Option<Value>>,
     sp: usize,
+    /// halts `run` with a stack overflow once the stack holds more values than this
+    max_stack_size: usize,
+    /// halts `run` with a runtime error once this many instructions have executed
+    max_instructions: Option<u64>,
+    /// halts `run` with a runtime error once it has been running for this long
+    timeout: Option<Duration>,
+    /// instructions executed since `run` started, including those of nested `run_until` calls
+    executed: u64,
+    /// when the current `run` times out
+    deadline: Option<Instant>,
+    /// lets `+` stringify the other operand when only one of them is a string
+    string_coercion: bool,
+    /// writes the stack and disassembles every instruction to `writer` before executing it
+    trace: bool,
+    /// writes a JSON object per instruction to `writer` before executing it
+    json_trace: bool,
+    /// writes the compile errors to `writer` as a JSON array instead of printing them to stderr
+    json_diagnostics: bool,
+    /// destination of `print`, stdout by default
+    writer: Box<dyn Write>,
+    /// destination of runtime error reports, stderr by default
+    error_writer: Box<dyn Write>,
+    /// source of `read_line()`, stdin when unset so the REPL and scripts share its buffer
+    reader: Option<Box<dyn BufRead>>,
+    globals: Globals,
+    /// globals defined with `const`, which can't be assigned or redefined
+    const_globals: HashSet<String>,
+    /// upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// tracks the objects that can form reference cycles
+    heap: Heap,
+    /// file the source came from, named in compile errors and stack traces when set
+    source_name: Option<String>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VM {
-    pub fn interpret(source: Vec<u8>) -> Result<()> {
+    pub fn new() -> Self {
+        let mut vm = Self {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: vec![],
+            sp: 0,
+            max_stack_size: MAX_STACK_SIZE,
+            max_instructions: None,
+            timeout: None,
+            executed: 0,
+            deadline: None,
+            string_coercion: false,
+            trace: std::env::var("RLOX_TRACE").is_ok_and(|value| value == "1"),
+            json_trace: false,
+            json_diagnostics: false,
+            writer: Box::new(std::io::stdout()),
+            error_writer: Box::new(std::io::stderr()),
+            reader: None,
+            globals: Globals::default(),
+            const_globals: HashSet::new(),
+            open_upvalues: vec![],
+            heap: Heap::new(),
+            source_name: None,
+        };
+        native::define_natives(&mut vm);
+        vm
+    }
+
+    /// Reads the next line from the reader without its line ending, `None` at the end of input.
+    pub(crate) fn read_line(&mut self) -> Result<Option<String>> {
+        let mut line = String::new();
+        let read = match &mut self.reader {
+            Some(reader) => reader.read_line(&mut line)?,
+            None => std::io::stdin().read_line(&mut line)?,
+        };
+        if read == 0 {
+            return Ok(None);
+        }
+
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+        Ok(Some(line))
+    }
+
+    /// Writes `text` where `print` does, flushed since there's no newline to flush it.
+    pub(crate) fn write(&mut self, text: &str) -> Result<()> {
+        write!(self.writer, "{text}")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Makes `function` callable from scripts as the global `name`.
+    pub fn define_native(&mut self, name: &'static str, arity: usize, function: NativeFn) {
+        let native = Native {
+            name,
+            arity,
+            function,
+        };
+        let object = Object {
+            object_type: ObjectType::OBJ_NATIVE(Rc::new(native)),
+        };
+        self.globals.insert(name, Value::VAL_OBJECT(object));
+    }
+
+    /// Defines a global before any script runs, which scripts may redefine like a native.
+    pub(crate) fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name, value);
+    }
+
+    pub(crate) fn remove_global(&mut self, name: &str) {
+        self.globals.remove(name);
+    }
+
+    /// Includes the math functions and the `PI` and `E` constants, which every VM starts with.
+    pub fn with_stdlib(mut self, stdlib: bool) -> Self {
+        if stdlib {
+            native::define_math(&mut self);
+        } else {
+            native::remove_math(&mut self);
+        }
+        self
+    }
+
+    pub fn with_max_instructions(mut self, max_instructions: u64) -> Self {
+        self.max_instructions = Some(max_instructions);
+        self
+    }
+
+    /// Unlike the instruction limit this is wall-clock time, checked every
+    /// `TIMEOUT_CHECK_INTERVAL` instructions so a run can overshoot it slightly.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// The stack only takes the memory it uses, this limits how far it can grow.
+    pub fn with_stack_size(mut self, max_stack_size: usize) -> Self {
+        self.max_stack_size = max_stack_size;
+        self
+    }
+
+    pub fn with_string_coercion(mut self, string_coercion: bool) -> Self {
+        self.string_coercion = string_coercion;
+        self
+    }
+
+    /// Traces execution to the writer, also enabled with `RLOX_TRACE=1`.
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Writes `{"ip": 4, "op": "add", "stack": [1, 2]}` to the writer before every instruction,
+    /// one object per line.
+    pub fn with_json_trace(mut self, json_trace: bool) -> Self {
+        self.json_trace = json_trace;
+        self
+    }
+
+    /// For editors: every compile writes one line with a JSON array of its errors, empty when
+    /// there were none, like `[{"line": 1, "column": 5, "severity": "error", "message": "..."}]`.
+    pub fn with_json_diagnostics(mut self, json_diagnostics: bool) -> Self {
+        self.json_diagnostics = json_diagnostics;
+        self
+    }
+
+    pub fn with_source_name(mut self, source_name: impl Into<String>) -> Self {
+        self.source_name = Some(source_name.into());
+        self
+    }
+
+    pub fn with_writer(mut self, writer: impl Write + 'static) -> Self {
+        self.writer = Box::new(writer);
+        self
+    }
+
+    pub fn with_error_writer(mut self, error_writer: impl Write + 'static) -> Self {
+        self.error_writer = Box::new(error_writer);
+        self
+    }
+
+    pub fn with_reader(mut self, reader: impl BufRead + 'static) -> Self {
+        self.reader = Some(Box::new(reader));
+        self
+    }
+
+    /// Collects garbage after every allocation, also enabled with `RLOX_GC_STRESS=1`.
+    pub fn with_gc_stress(mut self, gc_stress: bool) -> Self {
+        self.heap.stress = gc_stress;
+        self
+    }
+
+    pub fn interpret(&mut self, source: Vec<u8>) -> Result<()> {
+        // nothing to compile, so don't disassemble, trace or reset anything either
+        if is_blank(&source) {
+            return Ok(());
+        }
+
         let mut chunk = Chunk::default();
 
         let mut compiler = Compiler::new(&mut chunk);
-
-        if !compiler.compile(source) {
-            return Err(COMPILE_ERROR.into());
+        if let Some(source_name) = &self.source_name {
+            compiler.set_source_name(source_name.as_str());
         }
 
-        let stack = Self::init_stack();
-        let mut vm = Self {
-            chunk,
-            ip: 0,
-            stack,
-            sp: 0,
+        let compiled = if self.json_diagnostics {
+            let errors = compiler.collect_errors(source);
+            writeln!(self.writer, "{}", diagnostics_json(&errors))?;
+            errors.is_empty()
+        } else {
+            compiler.compile(source)
         };
+        if !compiled {
+            let (message, line) = compiler.first_error().unwrap_or(("Compilation failed.", 0));
+            return Err(COMPILE_ERROR {
+                message: message.to_string(),
+                line,
+            }
+            .into());
+        }
 
-        vm.run()?;
+        self.load(chunk);
+        self.run()?;
         Ok(())
     }
 
+    /// Interprets `source` and returns the value it left on the stack, `nil` when there's none
+    /// or the input is empty.
+    pub fn eval(&mut self, source: &str) -> Result<Value> {
+        if is_blank(source.as_bytes()) {
+            return Ok(Value::VAL_NIL);
+        }
+
+        self.interpret(source.as_bytes().to_vec())?;
+        let value = self.stack[..self.sp].last().cloned().flatten();
+        Ok(value.unwrap_or(Value::VAL_NIL))
+    }
+
+    /// Runs a chunk that didn't necessarily come from the compiler, so it's verified first.
+    pub fn run_chunk(&mut self, chunk: Chunk) -> Result<()> {
+        chunk.verify()?;
+        self.load(chunk);
+        self.run()
+    }
+
+    /// Prepares a fresh stack with `chunk` as the top-level script.
+    fn load(&mut self, chunk: Chunk) {
+        let script = Closure {
+            function: Rc::new(Function {
+                arity: 0,
+                defaults: vec![],
+                body: 0,
+                variadic: false,
+                upvalue_count: 0,
+                chunk,
+                name: String::new(),
+            }),
+            upvalues: vec![],
+            superclass: None,
+        };
+
+        self.reset_stack();
+        self.frames.push(CallFrame {
+            closure: Rc::new(script),
+            ip: 0,
+            slots: 0,
+        });
+    }
+
+    /// Every slot of the value stack used so far, the ones at and above `sp` are empty.
+    pub fn stack_slice(&self) -> &[Option<Value>] {
+        &self.stack
+    }
+
+    /// Index of the next free stack slot, which is also the number of values on the stack.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
     fn push(&mut self, value: Value) {
-        self.stack[self.sp] = Some(value);
+        match self.stack.get_mut(self.sp) {
+            Some(slot) => *slot = Some(value),
+            None => self.stack.push(Some(value)),
+        }
         self.sp += 1;
     }
 
-    fn pop(&mut self) -> &Value {
+    /// Takes the top value off the stack, leaving its slot empty.
+    fn pop(&mut self) -> Value {
         self.sp -= 1;
-        self.stack[self.sp].as_ref().unwrap()
+        self.stack[self.sp].take().unwrap()
     }
 
+    /// Runs the loaded script. The instruction limit and timeout cover the whole run, including
+    /// script functions the VM calls back into.
     fn run(&mut self) -> Result<()> {
+        self.executed = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.run_until(0)
+    }
+
+    /// Runs until the script returns, or until a return leaves `base` frames, which lets the VM
+    /// call back into a script function and wait for its result.
+    fn run_until(&mut self, base: usize) -> Result<()> {
         loop {
-            print!("        ");
-            for i in 0..self.sp {
-                print!("[ ");
-                print!("{:?}", self.stack[i].clone().unwrap());
-                print!(" ]");
+            // between instructions every live value is reachable from the roots
+            if self.heap.should_collect() {
+                self.collect_garbage();
             }
-            println!();
 
-            self.chunk.disassemble_instruction(self.ip);
+            if self.trace {
+                self.trace_instruction()?;
+            }
+            if self.json_trace {
+                self.trace_json()?;
+            }
             let instruction = self.read_instruction()?;
-            if instruction == OpCode::OP_RETURN {
-                return Ok(());
+
+            // no instruction pushes more than a few values, so checking between them is enough
+            if self.sp > self.max_stack_size {
+                return Err(self.runtime_error(anyhow!("Stack overflow.")));
+            }
+
+            self.executed += 1;
+            if let Some(max_instructions) = self.max_instructions {
+                if self.executed > max_instructions {
+                    return Err(self.runtime_error(anyhow!("Execution limit exceeded.")));
+                }
+            }
+            if let Some(deadline) = self.deadline {
+                if self.executed.is_multiple_of(TIMEOUT_CHECK_INTERVAL)
+                    && Instant::now() >= deadline
+                {
+                    return Err(self.runtime_error(anyhow!("Execution timed out.")));
+                }
             }
 
             match instruction {
@@ -79,155 +397,1243 @@ impl VM {
                     self.push(constant);
                 }
                 OpCode::OP_NEGATE => {
-                    let constant = (-self.pop().clone())?;
-                    self.push(constant);
+                    let &Value::VAL_NUMBER(n) = self.peek_at(0) else {
+                        let error = Self::nil_operand(&[self.peek_at(0)])
+                            .unwrap_or_else(|| anyhow!("Operand must be a number."));
+                        return Err(self.runtime_error(error));
+                    };
+                    self.pop();
+                    self.push(Value::VAL_NUMBER(-n));
                 }
                 OpCode::OP_TRUE => self.push(Value::VAL_BOOL(true)),
                 OpCode::OP_FALSE => self.push(Value::VAL_BOOL(false)),
+                OpCode::OP_EQUAL if matches!(self.operator_method("eq"), Some(Ok(_))) => {
+                    if let Err(e) = self.call_operator("eq") {
+                        return Err(self.runtime_error(e));
+                    }
+                }
                 OpCode::OP_EQUAL => {
-                    let b = self.pop().clone();
-                    let a = self.pop().clone();
-                    let equal = self.values_equal(a, b);
+                    let b = self.pop();
+                    let a = self.pop();
+                    let equal = Self::values_equal(&a, &b);
                     self.push(Value::VAL_BOOL(equal));
                 }
-                OpCode::OP_GREATER => self.binary_op(BinaryOp::Greater),
-                OpCode::OP_LESS => self.binary_op(BinaryOp::Less),
+                OpCode::OP_LESS if self.operator_method("lt").is_some() => {
+                    if let Err(e) = self.call_operator("lt") {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                // `a > b` is `b < a`, so it goes to the right operand's `lt`
+                OpCode::OP_GREATER if Self::operator_method_of(self.peek_at(0), "lt").is_some() => {
+                    self.stack.swap(self.sp - 1, self.sp - 2);
+                    if let Err(e) = self.call_operator("lt") {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_GREATER | OpCode::OP_LESS => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let op = match instruction {
+                        OpCode::OP_GREATER => BinaryOp::Greater,
+                        _ => BinaryOp::Less,
+                    };
+                    let Some(result) = Self::compare(&a, &b, op) else {
+                        return Err(self.runtime_error(anyhow!(
+                            "Operands must be two numbers or two strings."
+                        )));
+                    };
+                    self.push(Value::VAL_BOOL(result));
+                }
                 OpCode::OP_NIL => self.push(Value::VAL_NIL),
                 OpCode::OP_NOT => {
-                    let val = self.pop().clone();
-                    self.push(Value::VAL_BOOL(self.is_falsey(val)))
+                    let val = self.pop();
+                    self.push(Value::VAL_BOOL(val.is_falsey()))
+                }
+                OpCode::OP_ADD if self.operator_method("add").is_some() => {
+                    if let Err(e) = self.call_operator("add") {
+                        return Err(self.runtime_error(e));
+                    }
                 }
                 OpCode::OP_ADD => {
-                    if let Some(a) = self.peek_at(0) {
-                        if let Some(b) = self.peek_at(1) {
-                            match (a, b) {
-                                (Value::VAL_OBJECT(oa), Value::VAL_OBJECT(ob)) => {
-                                    if let ObjectType::OBJ_STRING(a) = &oa.object_type {
-                                        if let ObjectType::OBJ_STRING(b) = &oa.object_type {
-                                            self.concatenate()
-                                        }
-                                    }
-                                }
-                                _ => self.runtime_error(anyhow!(
-                                    "Operands must be either addable or concatenatable."
-                                )),
+                    let b = self.peek_at(0);
+                    let a = self.peek_at(1);
+                    match (a, b) {
+                        (Value::VAL_NUMBER(_), Value::VAL_NUMBER(_)) => {
+                            self.binary_op(BinaryOp::Add)?
+                        }
+                        (a, b) if a.as_string().is_some() && b.as_string().is_some() => {
+                            self.concatenate()
+                        }
+                        (a, b)
+                            if self.string_coercion
+                                && (a.as_string().is_some() || b.as_string().is_some()) =>
+                        {
+                            self.concatenate()
+                        }
+                        (a, b) => {
+                            let error = Self::nil_operand(&[a, b]).unwrap_or_else(|| {
+                                anyhow!("Operands must be either addable or concatenatable.")
+                            });
+                            return Err(self.runtime_error(error));
+                        }
+                    }
+                }
+                OpCode::OP_SUBTRACT => self.binary_op(BinaryOp::Sub)?,
+                OpCode::OP_MULTIPLY => self.binary_op(BinaryOp::Mul)?,
+                OpCode::OP_DIVIDE => self.binary_op(BinaryOp::Div)?,
+                OpCode::OP_POP => {
+                    self.pop();
+                }
+                OpCode::OP_TO_STRING => {
+                    if self.peek_at(0).as_string().is_none() {
+                        let value = self.pop();
+                        self.push(Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_STRING(value.to_string().into()),
+                        }));
+                    }
+                }
+                OpCode::OP_IS => {
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_CLASS(class),
+                    }) = self.peek_at(0).clone()
+                    else {
+                        return Err(
+                            self.runtime_error(anyhow!("Right operand of 'is' must be a class."))
+                        );
+                    };
+                    self.pop();
+                    let value = self.pop();
+                    self.push(Value::VAL_BOOL(Self::is_instance_of(&value, &class)));
+                }
+                OpCode::OP_RANGE => {
+                    let (Some(start), Some(end)) =
+                        (self.peek_at(1).as_number(), self.peek_at(0).as_number())
+                    else {
+                        return Err(self.runtime_error(anyhow!("Range bounds must be numbers.")));
+                    };
+                    self.pop();
+                    self.pop();
+                    let range = Range::new(start, end);
+                    self.push(Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_RANGE(Rc::new(range)),
+                    }));
+                }
+                OpCode::OP_ITER_NEXT => {
+                    // a for loop keeps what it iterates and the index of the next item on top
+                    let offset = self.read_short();
+                    let &Value::VAL_INDEX(index) = self.peek_at(0) else {
+                        return Err(self.runtime_error(anyhow!(
+                            "Expected a loop index below the iterated value."
+                        )));
+                    };
+                    let item = match self.peek_at(1) {
+                        Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_RANGE(range),
+                        }) => range.get(index).map(Value::VAL_NUMBER),
+                        Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_LIST(list),
+                        }) => list.borrow().get(index).cloned(),
+                        iterator @ Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_INSTANCE(_),
+                        }) => self.iterator_next(iterator.clone())?,
+                        iterable => {
+                            let message = format!("Can't iterate over a {}.", iterable.type_name());
+                            return Err(self.runtime_error(anyhow!(message)));
+                        }
+                    };
+
+                    match item {
+                        Some(item) => {
+                            self.stack[self.sp - 1] = Some(Value::VAL_INDEX(index + 1));
+                            self.push(item);
+                        }
+                        None => self.frame_mut().ip += offset as usize,
+                    }
+                }
+                OpCode::OP_POPN => {
+                    for _ in 0..self.read_byte() {
+                        self.pop();
+                    }
+                }
+                OpCode::OP_ASSERT => {
+                    let message = self.pop();
+                    let condition = self.pop();
+                    if condition.is_falsey() {
+                        return Err(self.runtime_error(anyhow!("{message}")));
+                    }
+                }
+                OpCode::OP_JUMP_IF_FALSE => {
+                    let offset = self.read_short();
+                    if self.peek_at(0).is_falsey() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::OP_JUMP => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset as usize;
+                }
+                OpCode::OP_LOOP => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset as usize;
+                }
+                OpCode::OP_PRINT => {
+                    let arg_count = self.read_byte() as usize;
+                    let mut values = Vec::with_capacity(arg_count);
+                    for _ in 0..arg_count {
+                        values.push(self.pop().to_string());
+                    }
+                    values.reverse();
+                    writeln!(self.writer, "{}", values.join(" "))?;
+                }
+                OpCode::OP_DEFINE_GLOBAL | OpCode::OP_DEFINE_CONST_GLOBAL => {
+                    let name = self.read_string();
+                    if self.const_globals.contains(&*name) {
+                        return Err(
+                            self.runtime_error(anyhow!("Can't redefine constant '{name}'."))
+                        );
+                    }
+                    if instruction == OpCode::OP_DEFINE_CONST_GLOBAL {
+                        self.const_globals.insert(name.to_string());
+                    }
+                    let value = self.pop();
+                    self.globals.insert(&name, value);
+                }
+                OpCode::OP_GET_GLOBAL => {
+                    let constant = self.read_byte() as usize;
+                    let Some(value) = self.get_global(constant) else {
+                        let name = self.string_constant(constant);
+                        return Err(self.runtime_error(anyhow!("Undefined variable '{name}'.")));
+                    };
+                    self.push(value);
+                }
+                OpCode::OP_SET_GLOBAL => {
+                    let name = self.read_string();
+                    if !self.globals.contains(&name) {
+                        return Err(self.runtime_error(anyhow!("Undefined variable '{name}'.")));
+                    }
+                    if self.const_globals.contains(&*name) {
+                        return Err(
+                            self.runtime_error(anyhow!("Can't assign to constant '{name}'."))
+                        );
+                    }
+                    let value = self.peek_at(0).clone();
+                    self.globals.insert(&name, value);
+                }
+                OpCode::OP_BUILD_LIST => {
+                    let item_count = self.read_byte() as usize;
+                    self.build_list(item_count);
+                }
+                OpCode::OP_BUILD_MAP => {
+                    let entry_count = self.read_byte() as usize;
+                    let mut entries = Vec::with_capacity(entry_count);
+                    for _ in 0..entry_count {
+                        let value = self.pop();
+                        let key = self.pop();
+                        entries.push((key, value));
+                    }
+
+                    let mut map = HashMap::with_capacity(entry_count);
+                    // entries were popped last to first, so later duplicates win
+                    for (key, value) in entries.into_iter().rev() {
+                        match Self::map_key(&key) {
+                            Ok(key) => map.insert(key, value),
+                            Err(e) => {
+                                return Err(self.runtime_error(e));
                             }
+                        };
+                    }
+
+                    let map = self.allocate(ObjectType::OBJ_MAP(Rc::new(RefCell::new(map))));
+                    self.push(map);
+                }
+                OpCode::OP_CLASS => {
+                    let name = self.read_string();
+                    let class =
+                        self.allocate(ObjectType::OBJ_CLASS(Rc::new(Class::new(name.to_string()))));
+                    self.push(class);
+                }
+                OpCode::OP_CALL => {
+                    let arg_count = self.read_byte() as usize;
+                    if let Err(e) = self.call_value(arg_count) {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_TAIL_CALL => {
+                    let arg_count = self.read_byte() as usize;
+                    if let Err(e) = self.tail_call(arg_count) {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_GET_PROPERTY => {
+                    let name = self.read_string();
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_INSTANCE(instance),
+                    }) = self.peek_at(0).clone()
+                    else {
+                        return Err(self.runtime_error(anyhow!("Only instances have properties.")));
+                    };
+
+                    // fields shadow methods of the same name
+                    let field = instance.borrow().fields.get(&*name).cloned();
+                    if let Some(value) = field {
+                        self.pop();
+                        self.push(value);
+                    } else {
+                        let class = instance.borrow().class.clone();
+                        if let Err(e) = self.bind_method(&class, &name) {
+                            return Err(self.runtime_error(e));
+                        }
+                    }
+                }
+                OpCode::OP_SET_PROPERTY => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_INSTANCE(instance),
+                    }) = self.pop()
+                    else {
+                        return Err(self.runtime_error(anyhow!("Only instances have fields.")));
+                    };
+
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.to_string(), value.clone());
+                    self.push(value);
+                }
+                OpCode::OP_INDEX_GET => {
+                    // the operands stay on the stack until they are known to be valid
+                    match Self::index_get(self.peek_at(1), self.peek_at(0)) {
+                        Ok(value) => {
+                            self.pop();
+                            self.pop();
+                            self.push(value);
                         }
+                        Err(e) => {
+                            return Err(self.runtime_error(e));
+                        }
+                    }
+                }
+                OpCode::OP_INDEX_SET => {
+                    let value = self.peek_at(0).clone();
+                    if let Err(e) = Self::index_set(self.peek_at(2), self.peek_at(1), value.clone())
+                    {
+                        return Err(self.runtime_error(e));
+                    }
+                    self.pop();
+                    self.pop();
+                    self.pop();
+                    self.push(value);
+                }
+                OpCode::OP_GET_LOCAL => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    let value = self.stack[slot].clone().unwrap();
+                    self.push(value);
+                }
+                OpCode::OP_SET_LOCAL => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = Some(self.peek_at(0).clone());
+                }
+                OpCode::OP_METHOD => {
+                    let name = self.read_string();
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_CLOSURE(closure),
+                    }) = self.pop()
+                    else {
+                        unreachable!("method must be a closure")
+                    };
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_CLASS(class),
+                    }) = self.peek_at(0)
+                    else {
+                        unreachable!("methods are defined right below their class")
+                    };
+
+                    let method = Closure {
+                        superclass: class.superclass.borrow().clone(),
+                        ..(*closure).clone()
+                    };
+                    class
+                        .methods
+                        .borrow_mut()
+                        .insert(name.to_string(), Rc::new(method));
+                }
+                OpCode::OP_INHERIT => {
+                    let subclass = self.pop();
+                    let superclass = self.pop();
+                    let (
+                        Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_CLASS(superclass),
+                        }),
+                        Value::VAL_OBJECT(Object {
+                            object_type: ObjectType::OBJ_CLASS(subclass),
+                        }),
+                    ) = (superclass, subclass)
+                    else {
+                        return Err(self.runtime_error(anyhow!("Superclass must be a class.")));
+                    };
+
+                    // methods are copied down, the subclass's own methods are added afterwards
+                    let inherited = superclass.methods.borrow().clone();
+                    subclass.methods.borrow_mut().extend(inherited);
+                    *subclass.superclass.borrow_mut() = Some(superclass);
+                }
+                OpCode::OP_GET_SUPER => {
+                    let name = self.read_string();
+                    let superclass = self.frame().closure.superclass.clone();
+                    let result = match superclass {
+                        Some(superclass) => self.bind_method(&superclass, &name),
+                        None => Err(anyhow!("Can't use 'super' outside of a method.")),
+                    };
+                    if let Err(e) = result {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_SUPER_INVOKE => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.frame().closure.superclass.clone();
+                    let result = match superclass {
+                        Some(superclass) => self.invoke_from_class(&superclass, &name, arg_count),
+                        None => Err(anyhow!("Can't use 'super' outside of a method.")),
+                    };
+                    if let Err(e) = result {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_INVOKE => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    if let Err(e) = self.invoke(&name, arg_count) {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_TAIL_INVOKE => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    if let Err(e) = self.tail_invoke(&name, arg_count) {
+                        return Err(self.runtime_error(e));
+                    }
+                }
+                OpCode::OP_CLOSURE => {
+                    let Value::VAL_OBJECT(Object {
+                        object_type: ObjectType::OBJ_FUNCTION(function),
+                    }) = self.read_constant()
+                    else {
+                        unreachable!("closure operand must be a function")
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.frame().closure.upvalues[index].clone()
+                        };
+                        upvalues.push(upvalue);
                     }
-                    self.binary_op(BinaryOp::Add)
+
+                    let closure = Closure {
+                        function,
+                        upvalues,
+                        superclass: self.frame().closure.superclass.clone(),
+                    };
+                    let object = Object {
+                        object_type: ObjectType::OBJ_CLOSURE(Rc::new(closure)),
+                    };
+                    self.push(Value::VAL_OBJECT(object));
+                }
+                OpCode::OP_GET_UPVALUE => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone().unwrap(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.push(value);
+                }
+                OpCode::OP_SET_UPVALUE => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[index].clone();
+                    let value = self.peek_at(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = Some(value),
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::OP_CLOSE_UPVALUE => {
+                    self.close_upvalues(self.sp - 1);
+                    self.pop();
                 }
-                OpCode::OP_SUBTRACT => self.binary_op(BinaryOp::Sub),
-                OpCode::OP_MULTIPLY => self.binary_op(BinaryOp::Mul),
-                OpCode::OP_DIVIDE => self.binary_op(BinaryOp::Div),
                 OpCode::OP_RETURN => {
-                    return Ok(());
+                    // the script's frame is kept, leaving its trailing expression on the stack
+                    if self.frames.len() == 1 {
+                        return Ok(());
+                    }
+
+                    let result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    self.close_upvalues(frame.slots);
+                    while self.sp > frame.slots {
+                        self.pop();
+                    }
+                    self.push(result);
+                    if self.frames.len() == base {
+                        return Ok(());
+                    }
                 }
             }
         }
     }
 
+    /// Prints the stack and the instruction about to run, without touching either.
+    fn trace_instruction(&mut self) -> Result<()> {
+        let stack = self.stack[..self.sp]
+            .iter()
+            .flatten()
+            .map(|value| format!("[ {value:?} ]"))
+            .collect::<String>();
+        let mut trace = format!("        {stack}\n");
+
+        // an instruction that can't be decoded is reported when it's read
+        let frame = self.frame();
+        let _ = frame
+            .closure
+            .function
+            .chunk
+            .write_instruction(&mut trace, frame.ip);
+        self.writer.write_all(trace.as_bytes())?;
+        Ok(())
+    }
+
+    fn trace_json(&mut self) -> Result<()> {
+        let frame = self.frame();
+        let op = match OpCode::decode(frame.closure.function.chunk.code[frame.ip]) {
+            Ok(op_code) => json_string(&op_code.to_string()),
+            Err(_) => "null".to_string(),
+        };
+        let stack = self.stack[..self.sp]
+            .iter()
+            .flatten()
+            .map(json_value)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let line = format!(
+            "{{\"ip\": {}, \"op\": {op}, \"stack\": [{stack}]}}\n",
+            frame.ip
+        );
+        self.writer.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
     fn read_byte(&mut self) -> Instruction {
-        let instruction = self.chunk.code[self.ip];
-        self.ip += 1;
+        let frame = self.frame_mut();
+        let instruction = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
         instruction
     }
 
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte() as u16;
+        let low = self.read_byte() as u16;
+        (high << 8) | low
+    }
+
     fn read_instruction(&mut self) -> Result<OpCode> {
         let instruction = self.read_byte();
-        let op_code = OpCode::try_from(instruction)?;
+        let op_code = OpCode::decode(instruction)?;
         Ok(op_code)
     }
 
+    /// Constants stay owned by their chunk. Every object a value can hold is behind an `Rc`, so
+    /// the copy pushed here only bumps a reference count and never copies a string's characters.
     fn read_constant(&mut self) -> Value {
-        let instruction = self.read_byte();
-        self.chunk.constants.values[instruction as usize].clone()
+        let constant = self.read_byte();
+        self.frame().closure.function.chunk.constants[constant as usize].clone()
     }
 
-    fn binary_op(&mut self, op: BinaryOp) {
-        let b = self.pop().clone();
-        let a = self.pop().clone();
-        let val = match op {
-            BinaryOp::Add => a + b,
-            BinaryOp::Sub => a - b,
-            BinaryOp::Div => a / b,
-            BinaryOp::Mul => a * b,
-            BinaryOp::Greater => {
-                self.push(Value::VAL_BOOL(a > b));
-                return;
-            }
-            BinaryOp::Less => {
-                self.push(Value::VAL_BOOL(a < b));
-                return;
+    fn read_string(&mut self) -> Rc<str> {
+        let constant = self.read_byte();
+        self.string_constant(constant as usize)
+    }
+
+    fn string_constant(&self, constant: usize) -> Rc<str> {
+        match &self.frame().closure.function.chunk.constants[constant] {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(string),
+            }) => string.clone(),
+            _ => unreachable!("constant operand must be a string"),
+        }
+    }
+
+    /// Reads the global named by `constant`, remembering its slot in the chunk so later reads
+    /// skip looking the name up. Redefining a global reuses its slot, so the remembered one stays
+    /// valid, and a removed global leaves its slot empty.
+    fn get_global(&self, constant: usize) -> Option<Value> {
+        let cache = self
+            .frame()
+            .closure
+            .function
+            .chunk
+            .global_cache
+            .get(constant);
+        let slot = match cache.and_then(Cell::get) {
+            Some(slot) => slot,
+            None => {
+                let slot = self.globals.slot(&self.string_constant(constant))?;
+                if let Some(cache) = cache {
+                    cache.set(Some(slot));
+                }
+                slot
             }
         };
-        match val {
-            Ok(val) => self.push(Value::VAL_NUMBER(val)),
-            Err(e) => self.runtime_error(e),
+        self.globals.get_slot(slot).cloned()
+    }
+
+    fn index_get(target: &Value, index: &Value) -> Result<Value> {
+        match target {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_LIST(list),
+            }) => {
+                let list = list.borrow();
+                let index = Self::list_index(index, list.len(), target.type_name())?;
+                Ok(list[index].clone())
+            }
+            // a missing key reads as nil
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_MAP(map),
+            }) => {
+                let key = Self::map_key(index)?;
+                Ok(map.borrow().get(&key).cloned().unwrap_or(Value::VAL_NIL))
+            }
+            // indexes count characters, not bytes
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(string),
+            }) => {
+                let index = Self::list_index(index, string.chars().count(), target.type_name())?;
+                let character = string.chars().nth(index).unwrap();
+                Ok(Value::VAL_OBJECT(Object {
+                    object_type: ObjectType::OBJ_STRING(character.to_string().into()),
+                }))
+            }
+            _ => Err(anyhow!(
+                "Can only index lists, maps and strings, got {}.",
+                target.type_name()
+            )),
+        }
+    }
+
+    fn index_set(target: &Value, index: &Value, value: Value) -> Result<()> {
+        match target {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_LIST(list),
+            }) => {
+                let mut list = list.borrow_mut();
+                let index = Self::list_index(index, list.len(), target.type_name())?;
+                list[index] = value;
+                Ok(())
+            }
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_MAP(map),
+            }) => {
+                let key = Self::map_key(index)?;
+                map.borrow_mut().insert(key, value);
+                Ok(())
+            }
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(_),
+            }) => Err(anyhow!("Strings can't be modified.")),
+            _ => Err(anyhow!(
+                "Can only assign through an index to lists and maps, got {}.",
+                target.type_name()
+            )),
         }
     }
 
-    fn runtime_error(&self, error: anyhow::Error) {
-        eprintln!("{error}");
+    /// Calls the value sitting below its `arg_count` arguments.
+    fn call_value(&mut self, arg_count: usize) -> Result<()> {
+        let callee_slot = self.sp - 1 - arg_count;
+        match self.peek_at(arg_count).clone() {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_CLOSURE(closure),
+            }) => self.call(closure, arg_count),
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_BOUND_METHOD(bound),
+            }) => {
+                self.stack[callee_slot] = Some(bound.receiver.clone());
+                self.call(bound.method.clone(), arg_count)
+            }
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_NATIVE(native),
+            }) => {
+                if arg_count != native.arity {
+                    return Err(anyhow!(
+                        "Expected {} arguments but got {arg_count}.",
+                        native.arity
+                    ));
+                }
+
+                let mut args = (0..arg_count).map(|_| self.pop()).collect::<Vec<_>>();
+                args.reverse();
+                self.pop();
 
-        let instruction = self.ip - self.sp - 1;
-        let line = self.chunk.lines[instruction];
+                let result = (native.function)(self, &args)?;
+                self.push(result);
+                Ok(())
+            }
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_CLASS(class),
+            }) => {
+                let instance = self.allocate(ObjectType::OBJ_INSTANCE(Rc::new(RefCell::new(
+                    Instance::new(class.clone()),
+                ))));
+                self.stack[callee_slot] = Some(instance);
 
-        eprintln!("[line {line}] in script");
+                let initializer = class.methods.borrow().get("init").cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        Err(anyhow!("Expected 0 arguments but got {arg_count}."))
+                    }
+                    None => Ok(()),
+                }
+            }
+            _ => Err(anyhow!("Can only call functions and classes.")),
+        }
     }
 
-    fn is_falsey(&self, value: Value) -> bool {
-        value == Value::VAL_NIL || value == Value::VAL_BOOL(false)
+    /// The method overloading a binary operator when its left operand is an instance, `None` when
+    /// it isn't one. Methods are copied down to subclasses, so inherited ones are found too.
+    fn operator_method(&self, name: &str) -> Option<Result<Rc<Closure>>> {
+        Self::operator_method_of(self.peek_at(1), name)
     }
-    fn values_equal(&self, a: Value, b: Value) -> bool {
-        match (a, b) {
-            (Value::VAL_BOOL(a), Value::VAL_BOOL(b)) => a == b,
-            (Value::VAL_NUMBER(a), Value::VAL_NUMBER(b)) => a == b,
-            (Value::VAL_OBJECT(oa), Value::VAL_OBJECT(ob)) => oa == ob,
-            (Value::VAL_NIL, Value::VAL_NIL) => true,
-            _ => false,
-        }
+
+    fn operator_method_of(operand: &Value, name: &str) -> Option<Result<Rc<Closure>>> {
+        let Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_INSTANCE(instance),
+        }) = operand
+        else {
+            return None;
+        };
+
+        let class = &instance.borrow().class;
+        let method = class.methods.borrow().get(name).cloned();
+        Some(method.ok_or_else(|| {
+            anyhow!(
+                "Undefined operator method '{name}' for {} instance.",
+                class.name
+            )
+        }))
     }
-    fn init_stack() -> [Option<Value>; MAX_STACK_SIZE] {
-        const STACK_INIT: Option<Value> = None;
-        [STACK_INIT; MAX_STACK_SIZE]
+
+    /// Calls the left operand's method `name` with the right operand, its result takes the place
+    /// of the operator's.
+    fn call_operator(&mut self, name: &str) -> Result<()> {
+        let method = self
+            .operator_method(name)
+            .expect("the left operand must be an instance")?;
+        self.call(method, 1)
     }
-    fn peek_at(&self, at: usize) -> &Option<Value> {
-        &self.stack[self.sp - at]
+
+    /// Calls `next()` on an instance a for loop iterates over and waits for the result. The
+    /// iterator protocol ends the loop once `next()` returns `nil`. Errors are already reported.
+    fn iterator_next(&mut self, iterator: Value) -> Result<Option<Value>> {
+        let base = self.frames.len();
+        self.push(iterator);
+        if let Err(e) = self.invoke("next", 0) {
+            return Err(self.runtime_error(e));
+        }
+        // a native stored in a `next` field has already returned
+        if self.frames.len() > base {
+            self.run_until(base)?;
+        }
+
+        Ok(match self.pop() {
+            Value::VAL_NIL => None,
+            item => Some(item),
+        })
     }
-    fn concatenate(&mut self) {
-        let b = self.pop().clone();
-        let a = self.pop().clone();
 
-        match (&a, &b) {
-            (Value::VAL_OBJECT(oa), Value::VAL_OBJECT(ob)) => {
-                match (oa.clone().object_type, ob.clone().object_type) {
-                    (ObjectType::OBJ_STRING(mut a), ObjectType::OBJ_STRING(b)) => a.push_str(&b),
-                    _ => unreachable!(),
-                }
-            }
-            _ => unreachable!(),
+    /// Replaces the top `item_count` values with a list of them.
+    fn build_list(&mut self, item_count: usize) {
+        let mut items = Vec::with_capacity(item_count);
+        for _ in 0..item_count {
+            items.push(self.pop());
         }
+        items.reverse();
 
-        let object = Object {
-            object_type: ObjectType::OBJ_STRING(a.to_string()),
+        let list = self.allocate(ObjectType::OBJ_LIST(Rc::new(RefCell::new(items))));
+        self.push(list);
+    }
+
+    /// Calls a closure in place of the function returning its result, so tail recursion runs in
+    /// constant frame and stack space. Any other callee gets an ordinary call and the `OP_RETURN`
+    /// that follows returns its result.
+    fn tail_call(&mut self, arg_count: usize) -> Result<()> {
+        let Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_CLOSURE(closure),
+        }) = self.peek_at(arg_count).clone()
+        else {
+            return self.call_value(arg_count);
         };
-        self.push(Value::VAL_OBJECT(object))
+        self.call_in_place(closure, arg_count)
     }
-}
 
-#[derive(Error, Debug)]
-pub enum InterpretError {
-    #[error("runtime error")]
-    RUNTIME_ERROR,
-    #[error("compilation error")]
-    COMPILE_ERROR,
-}
+    /// `invoke` for a method call whose result is returned, which reuses the returning frame
+    /// like `tail_call` does.
+    fn tail_invoke(&mut self, name: &str, arg_count: usize) -> Result<()> {
+        let Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_INSTANCE(instance),
+        }) = self.peek_at(arg_count).clone()
+        else {
+            return Err(anyhow!("Only instances have methods."));
+        };
 
-#[cfg(test)]
-mod tests {
-    use crate::op_code::OpCode::*;
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            let callee_slot = self.sp - 1 - arg_count;
+            self.stack[callee_slot] = Some(value);
+            return self.tail_call(arg_count);
+        }
 
-    use super::*;
+        let method = instance.borrow().class.methods.borrow().get(name).cloned();
+        match method {
+            Some(method) => self.call_in_place(method, arg_count),
+            None => Err(anyhow!("Undefined method '{name}'.")),
+        }
+    }
+
+    /// Calls `closure`, moving it or the receiver and the arguments into the returning frame's
+    /// slots and replacing that frame with the callee's.
+    fn call_in_place(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<()> {
+        // checked while the returning frame is still there to report the error from
+        Self::check_arity(&closure.function, arg_count)?;
+
+        // the callee and its arguments replace the returning frame's slots
+        let base = self.frame().slots;
+        let callee_slot = self.sp - 1 - arg_count;
+        self.close_upvalues(base);
+        for offset in 0..=arg_count {
+            self.stack[base + offset] = self.stack[callee_slot + offset].take();
+        }
+        let sp = base + arg_count + 1;
+        for slot in sp..self.sp {
+            self.stack[slot] = None;
+        }
+        self.sp = sp;
+
+        self.frames.pop();
+        self.call(closure, arg_count)
+    }
+
+    fn check_arity(function: &Function, arg_count: usize) -> Result<()> {
+        if arg_count < function.min_arity() || (arg_count > function.arity && !function.variadic) {
+            let name = match function.name.as_str() {
+                "" => "<anonymous>".to_string(),
+                name => format!("{name}()"),
+            };
+            let expected = match function.min_arity() {
+                min_arity if function.variadic => format!("at least {min_arity}"),
+                min_arity if min_arity == function.arity => min_arity.to_string(),
+                min_arity => format!("{min_arity} to {}", function.arity),
+            };
+            return Err(anyhow!(
+                "Expected {expected} arguments but got {arg_count} calling {name}."
+            ));
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, mut arg_count: usize) -> Result<()> {
+        let function = &closure.function;
+        Self::check_arity(function, arg_count)?;
+        if self.frames.len() == FRAMES_MAX {
+            return Err(anyhow!("Stack overflow."));
+        }
+
+        // the rest parameter takes every argument from its position on
+        if function.variadic && arg_count >= function.arity {
+            self.build_list(arg_count - (function.arity - 1));
+            arg_count = function.arity;
+        }
+
+        // missing arguments are filled in by their default initializers before the body
+        let ip = function.entry(arg_count);
+        self.frames.push(CallFrame {
+            closure,
+            ip,
+            slots: self.sp - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    /// Calls a method straight off the receiver below the arguments, without creating a bound method.
+    fn invoke(&mut self, name: &str, arg_count: usize) -> Result<()> {
+        let Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_INSTANCE(instance),
+        }) = self.peek_at(arg_count).clone()
+        else {
+            return Err(anyhow!("Only instances have methods."));
+        };
+
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            let callee_slot = self.sp - 1 - arg_count;
+            self.stack[callee_slot] = Some(value);
+            return self.call_value(arg_count);
+        }
+
+        let class = instance.borrow().class.clone();
+        self.invoke_from_class(&class, name, arg_count)
+    }
+
+    fn invoke_from_class(&mut self, class: &Class, name: &str, arg_count: usize) -> Result<()> {
+        let method = class.methods.borrow().get(name).cloned();
+        match method {
+            Some(method) => self.call(method, arg_count),
+            None => Err(anyhow!("Undefined method '{name}'.")),
+        }
+    }
+
+    /// Reuses the open upvalue for `slot` if a closure already captured it, so all closures share the variable.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open >= slot));
+        if let Some(position) = position {
+            let upvalue = &self.open_upvalues[position];
+            if matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot) {
+                return upvalue.clone();
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.heap.track_upvalue(&upvalue);
+        let position = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every captured variable at or above `last` off the stack and into its upvalue.
+    fn close_upvalues(&mut self, last: usize) {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open >= last))
+            .unwrap_or(self.open_upvalues.len());
+
+        for upvalue in self.open_upvalues.drain(position..) {
+            let mut upvalue = upvalue.borrow_mut();
+            if let Upvalue::Open(slot) = *upvalue {
+                *upvalue = Upvalue::Closed(self.stack[slot].clone().unwrap());
+            }
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method `name` bound to it.
+    fn bind_method(&mut self, class: &Class, name: &str) -> Result<()> {
+        let method = class.methods.borrow().get(name).cloned();
+        let Some(method) = method else {
+            return Err(anyhow!("Undefined property '{name}'."));
+        };
+
+        let receiver = self.pop();
+        let bound = Object {
+            object_type: ObjectType::OBJ_BOUND_METHOD(Rc::new(BoundMethod { receiver, method })),
+        };
+        self.push(Value::VAL_OBJECT(bound));
+        Ok(())
+    }
+
+    /// Wraps a new object in a value, registering it with the heap.
+    fn allocate(&mut self, object_type: ObjectType) -> Value {
+        self.heap.track(&object_type);
+        Value::VAL_OBJECT(Object { object_type })
+    }
+
+    /// Marks everything reachable from the stack, globals, open upvalues and call frames, then
+    /// empties the unreachable objects so their cycles get freed.
+    fn collect_garbage(&mut self) {
+        let values = self.stack[..self.sp]
+            .iter()
+            .flatten()
+            .chain(self.globals.values());
+        let closures = self.frames.iter().map(|frame| &frame.closure);
+        self.heap.collect(values, &self.open_upvalues, closures);
+    }
+
+    fn map_key(key: &Value) -> Result<String> {
+        match key {
+            Value::VAL_OBJECT(Object {
+                object_type: ObjectType::OBJ_STRING(key),
+            }) => Ok(key.to_string()),
+            // `-0 == 0`, so both name the same entry
+            Value::VAL_NUMBER(n) if *n == 0. => Ok(0.to_string()),
+            Value::VAL_NUMBER(n) => Ok(n.to_string()),
+            _ => Err(anyhow!("Map keys must be strings or numbers.")),
+        }
+    }
+
+    /// Checks that `index` is a whole number inside the `len` items of a list or string, named
+    /// by `type_name` in the error.
+    fn list_index(index: &Value, len: usize, type_name: &str) -> Result<usize> {
+        match index {
+            Value::VAL_NUMBER(n) if n.fract() != 0. => {
+                Err(anyhow!("Index must be a whole number."))
+            }
+            Value::VAL_NUMBER(n) if *n >= 0. && (*n as usize) < len => Ok(*n as usize),
+            Value::VAL_NUMBER(n) => Err(anyhow!(
+                "Index {n} is out of bounds for a {type_name} of length {len}."
+            )),
+            _ => Err(anyhow!("Index must be a whole number.")),
+        }
+    }
+
+    /// Applies an arithmetic operator to the two numbers on top of the stack. Anything else is left
+    /// on the stack and reported as a runtime error.
+    fn binary_op(&mut self, op: BinaryOp) -> Result<()> {
+        let (Some(a), Some(b)) = (self.peek_at(1).as_number(), self.peek_at(0).as_number()) else {
+            let error = Self::nil_operand(&[self.peek_at(1), self.peek_at(0)])
+                .unwrap_or_else(|| anyhow!("Operands must be numbers."));
+            return Err(self.runtime_error(error));
+        };
+        self.pop();
+        self.pop();
+
+        let result = match op {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Div => a / b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Greater | BinaryOp::Less => unreachable!("comparisons go through compare"),
+        };
+        self.push(Value::VAL_NUMBER(result));
+        Ok(())
+    }
+
+    /// A `nil` operand is most often a variable that was never assigned, so it gets an error of
+    /// its own rather than the generic type error.
+    fn nil_operand(operands: &[&Value]) -> Option<anyhow::Error> {
+        let nil = operands
+            .iter()
+            .find(|operand| matches!(operand, Value::VAL_NIL))?;
+        Some(anyhow!(
+            "Operand must be a number (got {}).",
+            nil.type_name()
+        ))
+    }
+
+    /// Reports `error` with a stack trace and turns it into an `InterpretError` at the current line.
+    fn runtime_error(&mut self, error: anyhow::Error) -> anyhow::Error {
+        let mut report = format!("{error}\n");
+
+        // the failing instruction, disassembled, then where each active call was made
+        let frame = self.frame();
+        if let Some(instruction) = frame
+            .closure
+            .function
+            .chunk
+            .instruction_containing(frame.ip - 1)
+        {
+            report.push_str(&instruction);
+        }
+        for (depth, frame) in self.frames.iter().enumerate().rev() {
+            let line = frame
+                .closure
+                .function
+                .chunk
+                .line_of(frame.ip - 1)
+                .unwrap_or(0);
+            let line = match &self.source_name {
+                Some(name) => format!("{name}:{line}"),
+                None => format!("line {line}"),
+            };
+            match frame.closure.function.name.as_str() {
+                _ if depth == 0 => report.push_str(&format!("[{line}] in script\n")),
+                "" => report.push_str(&format!("[{line}] in <anonymous>\n")),
+                name => report.push_str(&format!("[{line}] in {name}()\n")),
+            }
+        }
+        let _ = self.error_writer.write_all(report.as_bytes());
+
+        let frame = self.frame();
+        let line = frame
+            .closure
+            .function
+            .chunk
+            .line_of(frame.ip - 1)
+            .unwrap_or(0);
+        self.reset_stack();
+        RUNTIME_ERROR {
+            message: error.to_string(),
+            line,
+        }
+        .into()
+    }
+
+    /// Drops every value, call frame and open upvalue, so the next run starts from an empty stack.
+    fn reset_stack(&mut self) {
+        for slot in &mut self.stack[..self.sp] {
+            *slot = None;
+        }
+        self.sp = 0;
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    /// Orders two numbers, or two strings lexicographically by code point. Any other operands
+    /// can't be ordered and give `None`.
+    fn compare(a: &Value, b: &Value, op: BinaryOp) -> Option<bool> {
+        let ordering = match (a, b) {
+            // no ordering with NaN, which makes both comparisons false
+            (Value::VAL_NUMBER(a), Value::VAL_NUMBER(b)) => a.partial_cmp(b),
+            (a, b) => match (a.as_string(), b.as_string()) {
+                (Some(a), Some(b)) => Some(a.cmp(b)),
+                _ => return None,
+            },
+        };
+        let expected = match op {
+            BinaryOp::Greater => Ordering::Greater,
+            _ => Ordering::Less,
+        };
+        Some(ordering == Some(expected))
+    }
+
+    /// Whether `value` is an instance of `class` or of a class inheriting from it.
+    fn is_instance_of(value: &Value, class: &Rc<Class>) -> bool {
+        let Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_INSTANCE(instance),
+        }) = value
+        else {
+            return false;
+        };
+
+        let mut current = Some(instance.borrow().class.clone());
+        while let Some(ancestor) = current {
+            if Rc::ptr_eq(&ancestor, class) {
+                return true;
+            }
+            current = ancestor.superclass.borrow().clone();
+        }
+        false
+    }
+
+    fn values_equal(a: &Value, b: &Value) -> bool {
+        match (a, b) {
+            (Value::VAL_BOOL(a), Value::VAL_BOOL(b)) => a == b,
+            (Value::VAL_NUMBER(a), Value::VAL_NUMBER(b)) => a == b,
+            (Value::VAL_OBJECT(oa), Value::VAL_OBJECT(ob)) => oa == ob,
+            (Value::VAL_NIL, Value::VAL_NIL) => true,
+            _ => false,
+        }
+    }
+    /// `at` is the distance from the top of the stack, 0 being the top value
+    fn peek_at(&self, at: usize) -> &Value {
+        self.stack[self.sp - 1 - at].as_ref().unwrap()
+    }
+    /// Joins the two values on top of the stack, stringifying a non-string operand.
+    fn concatenate(&mut self) {
+        let b = self.pop();
+        let a = self.pop();
+
+        let object = Object {
+            object_type: ObjectType::OBJ_STRING(format!("{a}{b}").into()),
+        };
+        self.push(Value::VAL_OBJECT(object))
+    }
+}
+
+/// Whether `source` is nothing but the whitespace the scanner skips, which compiles to nothing.
+fn is_blank(source: &[u8]) -> bool {
+    source
+        .iter()
+        .all(|c| matches!(c, b' ' | b'\r' | b'\t' | b'\n'))
+}
+
+/// Numbers, strings, booleans and `nil` map to their JSON counterparts, other objects are
+/// stringified.
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::VAL_NUMBER(n) if n.is_finite() => n.to_string(),
+        Value::VAL_NUMBER(_) | Value::VAL_NIL => "null".to_string(),
+        Value::VAL_BOOL(b) => b.to_string(),
+        Value::VAL_INDEX(index) => index.to_string(),
+        Value::VAL_OBJECT(object) => json_string(&object.to_string()),
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::with_capacity(string.len() + 2);
+    json.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn diagnostics_json(errors: &[CompileError]) -> String {
+    let diagnostics = errors
+        .iter()
+        .map(|error| {
+            format!(
+                "{{\"line\": {}, \"column\": {}, \"severity\": \"error\", \"message\": {}}}",
+                error.line,
+                error.column,
+                json_string(&error.message)
+            )
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", diagnostics.join(", "))
+}
+
+#[derive(Error, Debug)]
+pub enum InterpretError {
+    #[error("[line {line}] {message}")]
+    RUNTIME_ERROR { message: String, line: usize },
+    #[error("[line {line}] {message}")]
+    COMPILE_ERROR { message: String, line: usize },
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::chunk::ChunkBuilder;
+    use crate::op_code::OpCode::*;
+
+    use super::*;
+
+    /// Shared buffer so tests can read back what the VM wrote.
+    #[derive(Clone, Default)]
+    pub(crate) struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
     #[test]
     fn binary_operands_should_succeed() {
@@ -252,18 +1658,1915 @@ mod tests {
 
         chunk.write(OP_RETURN.into(), 123);
 
-        let stack = VM::init_stack();
-        let mut vm = VM {
-            chunk,
-            ip: 0,
-            stack,
-            sp: 0,
-        };
+        let mut vm = VM::new();
+        vm.load(chunk);
 
         vm.run();
 
-        assert_eq!(vm.stack[0], Some(Value::VAL_NUMBER(-2.2)));
-        assert_eq!(vm.sp, 1);
-        assert_eq!(vm.ip, 10);
+        assert_eq!(vm.stack_slice()[0], Some(Value::VAL_NUMBER(-2.2)));
+        assert_eq!(vm.sp(), 1);
+        assert_eq!(vm.frame().ip, 10);
+    }
+
+    #[test]
+    fn eval_empty_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_error_writer(output.clone());
+
+        assert_eq!(vm.eval("").unwrap(), Value::VAL_NIL);
+        assert_eq!(vm.eval(" \n\t ").unwrap(), Value::VAL_NIL);
+        assert!(vm.interpret(b"\n\n".to_vec()).is_ok());
+        assert_eq!(output.contents(), "");
+        assert_eq!(vm.sp(), 0);
+
+        assert_eq!(vm.eval("print 1;").unwrap(), Value::VAL_NIL);
+        assert_eq!(output.contents(), "1\n");
+
+        // whitespace the scanner doesn't skip isn't empty to either of them
+        let mut vm = VM::new().with_error_writer(Output::default());
+        for source in ["\u{a0}", "\u{c}"] {
+            assert!(vm.eval(source).is_err(), "{source:?}");
+            assert!(
+                vm.interpret(source.as_bytes().to_vec()).is_err(),
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn assert_true_should_succeed() {
+        let result = VM::new().interpret("assert 1 == 1;".to_string().into_bytes());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn assert_false_should_fail() {
+        let result = VM::new().interpret("assert false;".to_string().into_bytes());
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { .. })
+        ));
+
+        let result =
+            VM::new().interpret(r#"assert 1 > 2, "one is not greater";"#.to_string().into_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn runtime_error_location_should_fail() {
+        let result = VM::new().interpret("var a = 1;\nprint a;\nprint b;".to_string().into_bytes());
+        let error = result.unwrap_err();
+        let Some(RUNTIME_ERROR { message, line }) = error.downcast_ref() else {
+            panic!("expected a runtime error, got {error}");
+        };
+        assert_eq!(message, "Undefined variable 'b'.");
+        assert_eq!(*line, 3);
+
+        let result = VM::new().interpret("var a = 1;\nvar = 2;".to_string().into_bytes());
+        let error = result.unwrap_err();
+        let Some(COMPILE_ERROR { message, line }) = error.downcast_ref() else {
+            panic!("expected a compile error, got {error}");
+        };
+        assert_eq!(message, "Expected variable name.");
+        assert_eq!(*line, 2);
+    }
+
+    #[test]
+    fn call_arity_should_fail() {
+        for (call, expected) in [
+            ("f(1);", "Expected 2 arguments but got 1 calling f()."),
+            ("f(1, 2, 3);", "Expected 2 arguments but got 3 calling f()."),
+            ("Point(1);", "Expected 0 arguments but got 1."),
+            ("abs();", "Expected 1 arguments but got 0."),
+        ] {
+            let source = format!("fun f(a, b) {{}}\nclass Point {{}}\n{call}");
+            let result = VM::new().interpret(source.into_bytes());
+
+            let error = result.unwrap_err();
+            let Some(RUNTIME_ERROR { message, line }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {call}, got {error}");
+            };
+            assert_eq!(message, expected);
+            // reported where the call is made
+            assert_eq!(*line, 3);
+        }
+    }
+
+    #[test]
+    fn lambda_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var double = fun (a) { return a * 2; };
+            print double(4);
+            fun apply(f, x) { return f(x); }
+            var offset = 1;
+            print apply(fun (x) { return x + offset; }, 2);
+            print fun () {};
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "8\n3\n<fn>\n");
+    }
+
+    #[test]
+    fn arrow_function_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var f = (x) => x + 1;
+            print f(4);
+            var k = 10;
+            var g = (a, b) => a * b + k;
+            print g(2, 3);
+            print (() => "none")();
+            print ((x) => (y) => x + y)(1)(2);
+            var a = 2;
+            print (a) + 1;
+            print a >= 2;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "5\n16\nnone\n3\n3\ntrue\n");
+    }
+
+    #[test]
+    fn default_parameters_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            fun greet(name = "world", end = "!") { print "hello " + name + end; }
+            greet();
+            greet("you");
+            greet("you", "?");
+            var calls = 0;
+            fun count(a, b = calls = calls + 1) { return b; }
+            print count(1);
+            print count(1);
+            print count(1, 10);
+            fun twice(a, b = a * 2) { return a + b; }
+            print twice(3);
+            class Point { init(x = 1) { this.x = x; } }
+            print Point().x;
+            print Point(5).x;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "hello world!\nhello you!\nhello you?\n1\n2\n10\n9\n1\n5\n"
+        );
+    }
+
+    #[test]
+    fn default_parameters_should_fail() {
+        for (call, expected) in [
+            ("f();", "Expected 1 to 2 arguments but got 0 calling f()."),
+            (
+                "f(1, 2, 3);",
+                "Expected 1 to 2 arguments but got 3 calling f().",
+            ),
+        ] {
+            let source = format!("fun f(a, b = 1) {{}}\n{call}");
+            let error = VM::new().interpret(source.into_bytes()).unwrap_err();
+
+            let Some(RUNTIME_ERROR { message, .. }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {call}, got {error}");
+            };
+            assert_eq!(message, expected);
+        }
+
+        // only trailing parameters can have a default
+        let error = VM::new()
+            .interpret("fun f(a = 1, b) {}".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(COMPILE_ERROR { message, .. })
+                if message == "Parameters after one with a default need a default too."
+        ));
+    }
+
+    #[test]
+    fn variadic_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            fun f(a, ...rest) { print rest; }
+            f(1);
+            f(1, 2);
+            f(1, 2, 3, 4);
+            fun count(...all) { return len(all); }
+            print count();
+            print count(nil, nil);
+            fun g(a, b = 5, ...rest) { print [a, b, rest]; }
+            g(1);
+            g(1, 2, 3);
+            var last = (...xs) => xs[len(xs) - 1];
+            print last(1, 2, 3);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "[]\n[2]\n[2, 3, 4]\n0\n2\n[1, 5, []]\n[1, 2, [3]]\n3\n"
+        );
+    }
+
+    #[test]
+    fn variadic_should_fail() {
+        let error = VM::new()
+            .interpret("fun f(a, ...rest) {}\nf();".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. })
+                if message == "Expected at least 1 arguments but got 0 calling f()."
+        ));
+
+        let error = VM::new()
+            .interpret("fun f(...rest, a) {}".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(COMPILE_ERROR { message, .. })
+                if message == "The rest parameter must be the last one."
+        ));
+    }
+
+    #[test]
+    fn lambda_should_fail() {
+        let errors = Output::default();
+        let mut vm = VM::new().with_error_writer(errors.clone());
+        let source = "var f = fun (a) {\n  return -a;\n};\nf(nil);\nf();";
+        let result = vm.interpret(source.to_string().into_bytes());
+
+        assert!(result.is_err());
+        assert!(errors.contents().ends_with(
+            "[line 2] in <anonymous>\n\
+             [line 4] in script\n"
+        ));
+
+        let error = VM::new()
+            .interpret("var f = fun (a) {};\nf();".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. })
+                if message == "Expected 1 arguments but got 0 calling <anonymous>."
+        ));
+    }
+
+    #[test]
+    fn source_name_should_fail() {
+        let errors = Output::default();
+        let mut vm = VM::new()
+            .with_source_name("main.lox")
+            .with_error_writer(errors.clone());
+        let source = "fun f() {\n  return -nil;\n}\nf();";
+        let result = vm.interpret(source.to_string().into_bytes());
+
+        assert!(result.is_err());
+        assert!(errors.contents().ends_with(
+            "[main.lox:2] in f()\n\
+             [main.lox:4] in script\n"
+        ));
+    }
+
+    #[test]
+    fn max_instructions_should_halt_infinite_loop() {
+        let mut vm = VM::new().with_max_instructions(100);
+        let result = vm.interpret("while (true) {}".to_string().into_bytes());
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { .. })
+        ));
+    }
+
+    #[test]
+    fn max_instructions_should_halt_iterator() {
+        let mut vm = VM::new()
+            .with_max_instructions(300)
+            .with_error_writer(Output::default());
+        let result = vm.interpret(
+            r#"
+            class Items {
+                init() { this.count = 0; }
+                next() {
+                    var i = 0;
+                    while (i < 20) i = i + 1;
+                    this.count = this.count + 1;
+                    while (this.count > 30) return nil;
+                    return this.count;
+                }
+            }
+            for item in Items() {}
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. }) if message == "Execution limit exceeded."
+        ));
+    }
+
+    #[test]
+    fn timeout_should_halt_infinite_loop() {
+        let timeout = Duration::from_millis(50);
+        let mut vm = VM::new()
+            .with_timeout(timeout)
+            .with_error_writer(Output::default());
+        let start = Instant::now();
+        let result = vm.interpret("while (true) {}".to_string().into_bytes());
+        let elapsed = start.elapsed();
+
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. }) if message == "Execution timed out."
+        ));
+        assert!(elapsed >= timeout);
+        assert!(elapsed < Duration::from_secs(2), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn timeout_should_succeed() {
+        let mut vm = VM::new().with_timeout(Duration::from_secs(10));
+        let result = vm.interpret(
+            "var i = 0; while (i < 5000) { i = i + 1; }"
+                .to_string()
+                .into_bytes(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn while_loop_should_succeed() {
+        let mut vm = VM::new().with_max_instructions(100);
+        let result = vm.interpret("while (false) { assert false; }".to_string().into_bytes());
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn type_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            assert type(1) == "number";
+            assert type("a") == "string";
+            class A { f() {} }
+            fun f() {}
+            print type(nil), type(true), type([]), type({}), type(A), type(A());
+            print type(f), type(A().f), type(type), type;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "nil bool list map class instance\nfunction function function <native fn type>\n"
+        );
+
+        let result = VM::new().interpret("type(1, 2);".to_string().into_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn interpolation_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            print "a ${1+1} b";
+            var name = "x";
+            print "${name}${name}";
+            print "${ "in ${ [1, 2][1] }" } ${ {"k": nil}["k"] } \${}";
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "a 2 b\nxx\nin 2 nil ${}\n");
+    }
+
+    #[test]
+    fn read_constant_should_succeed() {
+        let string: Rc<str> = "constant".into();
+        let mut chunk = Chunk::default();
+        let value = chunk.add_constant(Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING(string.clone()),
+        }));
+        let name = chunk.add_constant(Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING("a".into()),
+        }));
+        for _ in 0..2 {
+            chunk.write(OP_CONSTANT.into(), 1);
+            chunk.write(value as u8, 1);
+        }
+        chunk.write(OP_DEFINE_GLOBAL.into(), 1);
+        chunk.write(name as u8, 1);
+        chunk.write(OP_POP.into(), 1);
+        chunk.write(OP_RETURN.into(), 1);
+
+        let mut vm = VM::new();
+        assert!(vm.run_chunk(chunk).is_ok());
+
+        // the global shares its characters with the constant instead of holding a copy
+        let Some(Value::VAL_OBJECT(Object {
+            object_type: ObjectType::OBJ_STRING(global),
+        })) = vm.globals.get("a")
+        else {
+            panic!("expected a string global");
+        };
+        assert!(Rc::ptr_eq(global, &string));
+    }
+
+    #[test]
+    fn tail_call_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+
+        let result = vm.interpret(
+            r#"
+            fun countdown(n) {
+              while (n > 0) return countdown(n - 1);
+              return "done";
+            }
+            print countdown(100000);
+
+            fun sum(n, total) {
+              var get = fun() { return n; };
+              while (n > 0) return sum(n - 1, total + get());
+              return total;
+            }
+            print sum(1000, 0);
+
+            fun size(text) { return len(text); }
+            print size("abc");
+
+            class Counter {
+              init() { this.step = fun(n) { return n; }; }
+              down(n) {
+                while (n > 0) return this.down(n - 1);
+                return this.step(n);
+              }
+            }
+            print Counter().down(100000);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(output.contents(), "done\n500500\n3\n0\n");
+        assert_eq!(vm.sp(), 0);
+    }
+
+    #[test]
+    fn tail_call_should_fail() {
+        let result = VM::new().with_error_writer(Output::default()).interpret(
+            "fun f(n) { while (n > 0) return 1 + f(n - 1); return 0; }\nf(100000);"
+                .to_string()
+                .into_bytes(),
+        );
+        let error = result.unwrap_err();
+        let Some(RUNTIME_ERROR { message, .. }) = error.downcast_ref() else {
+            panic!("expected a runtime error, got {error}");
+        };
+        assert_eq!(message, "Stack overflow.");
+
+        // the returning frame is still there to report a bad tail call from
+        let result = VM::new().with_error_writer(Output::default()).interpret(
+            "fun g(a) { return a; }\nfun f() {\n  return g();\n}\nf();"
+                .to_string()
+                .into_bytes(),
+        );
+        let error = result.unwrap_err();
+        let Some(RUNTIME_ERROR { message, line }) = error.downcast_ref() else {
+            panic!("expected a runtime error, got {error}");
+        };
+        assert_eq!(message, "Expected 1 arguments but got 0 calling g().");
+        assert_eq!(*line, 3);
+    }
+
+    #[test]
+    fn global_cache_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+
+        let result = vm.interpret(
+            "var a = 1; fun f() { return a; } var i = 0; var sum = 0;
+            while (i < 3) { sum = sum + a; i = i + 1; }
+            var a = 10;
+            print sum, f(), a;"
+                .to_string()
+                .into_bytes(),
+        );
+        assert!(result.is_ok());
+        // the reads after the redefinition use the cached slot and see the new value
+        assert_eq!(output.contents(), "3 10 10\n");
+
+        let chunk = &vm.frame().closure.function.chunk;
+        let slots = (0..chunk.constants.len())
+            .filter(|&constant| chunk.constants[constant].to_string() == "a")
+            .filter_map(|constant| chunk.global_cache.get(constant)?.get())
+            .collect::<Vec<_>>();
+        assert!(!slots.is_empty());
+        assert_eq!(vm.globals.get_slot(slots[0]), Some(&Value::VAL_NUMBER(10.)));
+    }
+
+    #[test]
+    fn string_index_should_succeed() {
+        let result = VM::new().interpret(
+            r#"
+            assert len("abc") == 3;
+            assert len("") == 0;
+            assert len("héllo") == 5;
+            assert len([1, 2]) == 2;
+            assert len({"a": 1}) == 1;
+            assert "abc"[1] == "b";
+            assert "héllo"[1] == "é";
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+        assert!(result.is_ok());
+
+        let error = VM::new()
+            .interpret(r#""abc"[3];"#.to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(RUNTIME_ERROR { message, .. })
+                if message == "Index 3 is out of bounds for a string of length 3."
+        ));
+
+        for source in [r#""abc"[-1];"#, r#"var s = "abc"; s[0] = "x";"#, "len(1);"] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(RUNTIME_ERROR { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn substr_should_succeed() {
+        let result = VM::new().interpret(
+            r#"
+            assert substr("hello world", 6, 5) == "world";
+            assert substr("hello", 1, 100) == "ello";
+            assert substr("hello", 5, 1) == "";
+            assert substr("héllo wörld", 1, 4) == "éllo";
+            assert substr("日本語", 2, 1) == "語";
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+        assert!(result.is_ok());
+
+        for source in [
+            r#"substr("abc", 4, 1);"#,
+            r#"substr("abc", -1, 1);"#,
+            r#"substr("abc", 0, 1.5);"#,
+            "substr(1, 0, 1);",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(RUNTIME_ERROR { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn number_should_succeed() {
+        let result = VM::new().interpret(
+            r#"
+            assert number("3.14") == 3.14;
+            assert number(" 42 ") == 42;
+            assert number("-7") == -7;
+            assert number("0x10") == 16;
+            assert number("-0xff") == -255;
+            assert number("abc") == nil;
+            assert number("") == nil;
+            assert number("1.5x") == nil;
+            assert number("0x") == nil;
+            assert number("inf") == nil;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+        assert!(result.is_ok());
+
+        let result = VM::new().interpret("number(1);".to_string().into_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn read_line_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_reader(std::io::Cursor::new("Ada\r\nLovelace\n"));
+        let result = vm.interpret(
+            r#"
+            print "Hello, " + read_line() + "!";
+            print read_line(), read_line();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "Hello, Ada!\nLovelace nil\n");
+    }
+
+    #[test]
+    fn operator_overloading_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            class Vector {
+                init(x, y) { this.x = x; this.y = y; }
+                add(other) { return Vector(this.x + other.x, this.y + other.y); }
+                eq(other) { return this.x == other.x; }
+                lt(other) { return this.x < other.x; }
+            }
+            var v = Vector(1, 2) + Vector(3, 4);
+            print v.x, v.y;
+            print Vector(1, 2) == Vector(1, 5), Vector(1, 2) < Vector(0, 2);
+            print Vector(1, 2) > Vector(0, 2), Vector(1, 2) <= Vector(0, 2), Vector(0, 2) <= Vector(0, 2);
+            class Point < Vector {}
+            print (Point(1, 1) + Point(2, 2)).x;
+            class Plain {}
+            var plain = Plain();
+            print plain == plain, plain == Plain();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "4 6\ntrue false\ntrue false true\n3\ntrue false\n"
+        );
+    }
+
+    #[test]
+    fn operator_overloading_should_fail() {
+        for (source, expected) in [
+            (
+                "class A {}\nA() + 1;",
+                "Undefined operator method 'add' for A instance.",
+            ),
+            (
+                "class A {}\nA() < 1;",
+                "Undefined operator method 'lt' for A instance.",
+            ),
+            (
+                "class A {}\n1 > A();",
+                "Undefined operator method 'lt' for A instance.",
+            ),
+            (
+                "class A { add() {} }\nA() + 1;",
+                "Expected 0 arguments but got 1 calling add().",
+            ),
+        ] {
+            let error = VM::new()
+                .interpret(source.to_string().into_bytes())
+                .unwrap_err();
+            let Some(RUNTIME_ERROR { message, line }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {source}, got {error}");
+            };
+            assert_eq!(message, expected);
+            assert_eq!(*line, 2);
+        }
+    }
+
+    #[test]
+    fn is_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            class Animal {}
+            class Dog < Animal {}
+            class Cat < Animal {}
+            var dog = Dog();
+            print dog is Dog, dog is Animal, dog is Cat;
+            print Animal() is Dog, 1 is Animal, nil is Animal, Dog is Dog;
+            print !(dog is Dog) == false;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "true true false\nfalse false false false\ntrue\n"
+        );
+    }
+
+    #[test]
+    fn is_should_fail() {
+        let error = VM::new()
+            .interpret("class A {}\nA() is A();".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, line: 2 })
+                if message == "Right operand of 'is' must be a class."
+        ));
+    }
+
+    #[test]
+    fn range_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var count = 0;
+            for i in 0..10 count = count + 1;
+            print count;
+            for i in 2 - 1..1 + 3 { write(i); }
+            print "";
+            for i in 3..0 write(i);
+            print "";
+            for i in 5..5 print "never";
+            outer: for i in 0..10 {
+                for j in 0..10 {
+                    write(j);
+                    break outer;
+                }
+            }
+            print "";
+            var range = 1..4;
+            print range, type(range), range == 1..4, range == 1..5;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "10\n123\n321\n0\n1..4 range true false\n"
+        );
+    }
+
+    #[test]
+    fn range_should_fail() {
+        for (source, expected) in [
+            ("print 1..\"a\";", "Range bounds must be numbers."),
+            ("for i in 3 print i;", "Can't iterate over a number."),
+        ] {
+            let error = VM::new()
+                .interpret(source.to_string().into_bytes())
+                .unwrap_err();
+            let Some(RUNTIME_ERROR { message, .. }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {source}, got {error}");
+            };
+            assert_eq!(message, expected);
+        }
+    }
+
+    #[test]
+    fn foreach_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            for x in 0..4 write(x);
+            print "";
+            for x in [0, 1, 2, 3] write(x);
+            print "";
+            for x in [nil, false] write(x);
+            print "";
+            for x in [] print "never";
+            class Countdown {
+                init(n) { this.n = n; }
+                next() {
+                    while (this.n == 0) return nil;
+                    this.n = this.n - 1;
+                    return this.n + 1;
+                }
+            }
+            for x in Countdown(3) write(x);
+            print "";
+            var closures = [nil, nil];
+            var i = 0;
+            for x in ["a", "b"] {
+                closures[i] = fun () { return x; };
+                i = i + 1;
+            }
+            print closures[0](), closures[1]();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok(), "{result:?}");
+        assert_eq!(output.contents(), "0123\n0123\nnilfalse\n321\na b\n");
+    }
+
+    #[test]
+    fn foreach_should_fail() {
+        let errors = Output::default();
+        let mut vm = VM::new().with_error_writer(errors.clone());
+        let source = "class Bad {\n  next() { return -nil; }\n}\nfor x in Bad() print x;";
+        let error = vm.interpret(source.to_string().into_bytes()).unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, line: 2 })
+                if message == "Operand must be a number (got nil)."
+        ));
+        assert!(errors.contents().ends_with(
+            "[line 2] in next()\n\
+             [line 4] in script\n"
+        ));
+
+        let error = VM::new()
+            .interpret("class A {}\nfor x in A() print x;".to_string().into_bytes())
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. }) if message == "Undefined method 'next'."
+        ));
+    }
+
+    /// `for item in 0..16777218 print item;` resumed at `index`, past which an `f32` can't count
+    /// by one.
+    fn iterate_from(index: Value) -> ChunkBuilder {
+        ChunkBuilder::new()
+            .constant(Value::VAL_NUMBER(0.))
+            .constant(Value::VAL_NUMBER(16_777_218.))
+            .op(OP_RANGE)
+            .constant(index)
+            .op(OP_ITER_NEXT)
+            .operand(0)
+            .operand(5)
+            .op(OP_PRINT)
+            .operand(1)
+            .op(OP_LOOP)
+            .operand(0)
+            .operand(8)
+            .op(OP_RETURN)
+    }
+
+    #[test]
+    fn iterate_large_index_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_max_instructions(100);
+        let chunk = iterate_from(Value::VAL_INDEX(16_777_216)).build();
+
+        assert!(vm.run_chunk(chunk).is_ok());
+        // the range's numbers round, but the loop still counts on to its end
+        assert_eq!(output.contents(), "16777216\n16777216\n");
+    }
+
+    #[test]
+    fn iterate_large_index_should_fail() {
+        let mut vm = VM::new().with_error_writer(Output::default());
+        let chunk = iterate_from(Value::VAL_NUMBER(0.)).build();
+
+        let error = vm.run_chunk(chunk).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { message, .. })
+                if message == "Expected a loop index below the iterated value."
+        ));
+    }
+
+    #[test]
+    fn write_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            write("a");
+            write(1);
+            print write(nil);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "a1nilnil\n");
+    }
+
+    #[test]
+    fn utf8_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"var café = "crème 🎉"; print café, len(café), café[6];"#
+                .to_string()
+                .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "crème 🎉 7 🎉\n");
+    }
+
+    #[test]
+    fn invalid_utf8_should_fail() {
+        for source in [
+            vec![0xFF],
+            b"print \"a\xFE\";".to_vec(),
+            b"var \xC3 = 1;".to_vec(),
+        ] {
+            let result = VM::new().interpret(source);
+            let error = result.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref(),
+                Some(COMPILE_ERROR { message, .. }) if message == "Invalid UTF-8."
+            ));
+        }
+    }
+
+    #[test]
+    fn labeled_break_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var i = 0;
+            var inner = 0;
+            outer: while (i < 3) {
+                i = i + 1;
+                var local = i;
+                while (true) {
+                    inner = inner + local;
+                    break outer;
+                }
+                i = 100;
+            }
+            print i, inner;
+
+            var continued = 0;
+            var j = 0;
+            outer: while (j < 3) {
+                j = j + 1;
+                while (true) {
+                    var shadow = j;
+                    continued = continued + shadow;
+                    continue outer;
+                }
+            }
+            print continued;
+
+            var k = 0;
+            while (true) {
+                while (true) { break; }
+                k = k + 1;
+                break;
+            }
+            print k;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "1 1\n6\n1\n");
+    }
+
+    #[test]
+    fn labeled_break_should_fail() {
+        for source in [
+            "break;",
+            "continue;",
+            "outer: while (true) { break inner; }",
+            "outer: while (true) { fun f() { break outer; } }",
+            "outer: print 1;",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(COMPILE_ERROR { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn string_coercion_should_succeed() {
+        let mut vm = VM::new().with_string_coercion(true);
+        let result = vm.interpret(
+            r#"assert "x=" + 5 == "x=5"; assert 5 + "x" == "5x";"#
+                .to_string()
+                .into_bytes(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn strict_concatenation_should_fail() {
+        let result = VM::new().interpret(r#"assert "a" + "b" == "ab";"#.to_string().into_bytes());
+        assert!(result.is_ok());
+
+        let result = VM::new().interpret(r#""x=" + 5;"#.to_string().into_bytes());
+        let error = result.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<InterpretError>(),
+            Some(RUNTIME_ERROR { .. })
+        ));
+    }
+
+    #[test]
+    fn print_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(r#"print 1, 2, 3; print "a" + "b";"#.to_string().into_bytes());
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "1 2 3\nab\n");
+    }
+
+    #[test]
+    fn list_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var list = [1, "two", 3];
+            assert list[0] == 1;
+            assert list[1] == "two";
+            list[1] = 2;
+            assert list[1] == 2;
+            print list, [];
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "[1, 2, 3] []\n");
+    }
+
+    #[test]
+    fn list_index_out_of_bounds_should_fail() {
+        for source in [
+            "[1, 2][2];",
+            "[1, 2][-1];",
+            "[1, 2][0.5];",
+            "var list = [1]; list[1] = 2;",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<InterpretError>(),
+                Some(RUNTIME_ERROR { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn index_type_should_fail() {
+        for (source, expected) in [
+            (
+                "5[0];",
+                "Can only index lists, maps and strings, got number.",
+            ),
+            (
+                "nil[\"a\"];",
+                "Can only index lists, maps and strings, got nil.",
+            ),
+            (
+                "var a = true; a[0] = 1;",
+                "Can only assign through an index to lists and maps, got bool.",
+            ),
+        ] {
+            let errors = Output::default();
+            let mut vm = VM::new().with_error_writer(errors.clone());
+            let error = vm.interpret(source.to_string().into_bytes()).unwrap_err();
+
+            let Some(RUNTIME_ERROR { message, .. }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {source}, got {error}");
+            };
+            assert_eq!(message, expected);
+            assert!(errors.contents().starts_with(expected), "{source}");
+        }
+    }
+
+    #[test]
+    fn collection_equality_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            print [1, 2] == [1, 2], [1, 2] == [1, 2, 3], [1, 2] == [2, 1];
+            print [[1, [2]], {"a": [3]}] == [[1, [2]], {"a": [3]}], [[1]] == [[2]];
+            print {"a": 1, "b": 2} == {"b": 2, "a": 1}, {"a": 1} == {"a": 2}, {"a": 1} == {};
+            var list = [1];
+            list[0] = list;
+            print list == list, [] == {};
+            var a = [1]; a[0] = a;
+            var b = [1]; b[0] = b;
+            var c = [2, 1]; c[1] = c;
+            var m = {"m": 1}; m["m"] = m;
+            var n = {"m": 1}; n["m"] = n;
+            print a == b, a == c, m == n, [a] == [b];
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "true false false\ntrue false\ntrue false false\ntrue false\ntrue false true true\n"
+        );
+    }
+
+    #[test]
+    fn print_cyclic_list_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var a = [1];
+            a[0] = a;
+            var b = [a, [2]];
+            print a, b, [a, a];
+            print "${a}";
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "[[...]] [[[...]], [2]] [[[...]], [[...]]]\n[[...]]\n"
+        );
+    }
+
+    #[test]
+    fn print_cyclic_map_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var m = {};
+            m["x"] = m;
+            var l = [m];
+            m["l"] = l;
+            print m, l;
+            print "${m}";
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(
+            output.contents(),
+            "{l: [{...}], x: {...}} [{l: [...], x: {...}}]\n{l: [{...}], x: {...}}\n"
+        );
+    }
+
+    #[test]
+    fn map_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var map = {"a": 1, 2: "two", "a": 3};
+            assert map["a"] == 3;
+            assert map[2] == "two";
+            assert map["missing"] == nil;
+            map["b"] = 4;
+            assert map["b"] == 4;
+            map[-0] = "zero";
+            assert map[0] == "zero";
+            print map, {};
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "{0: zero, 2: two, a: 3, b: 4} {}\n");
+
+        let result = VM::new().interpret("var map = {nil: 1};".to_string().into_bytes());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn pop_should_succeed() {
+        let mut vm = VM::new();
+        vm.push(Value::VAL_NUMBER(1.));
+        vm.push(Value::VAL_BOOL(true));
+
+        assert_eq!(vm.pop(), Value::VAL_BOOL(true));
+        assert!(vm.stack_slice()[1].is_none());
+        assert_eq!(vm.sp(), 1);
+        assert_eq!(vm.pop(), Value::VAL_NUMBER(1.));
+        assert!(vm.stack_slice()[0].is_none());
+    }
+
+    #[test]
+    fn stack_size_should_succeed() {
+        let mut vm = VM::new().with_stack_size(2048);
+        for i in 0..1000 {
+            vm.push(Value::VAL_NUMBER(i as f32));
+        }
+
+        assert_eq!(vm.sp(), 1000);
+        assert_eq!(vm.stack_slice()[999], Some(Value::VAL_NUMBER(999.)));
+        assert_eq!(vm.pop(), Value::VAL_NUMBER(999.));
+        assert!(vm.stack_slice()[999].is_none());
+    }
+
+    #[test]
+    fn stack_size_should_fail() {
+        let source =
+            "fun f(n) { var a = 1; var b = 2; while (n > 0) return 1 + f(n - 1); return 0; }
+            print f(10);";
+
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        assert!(vm.interpret(source.to_string().into_bytes()).is_ok());
+        assert_eq!(output.contents(), "10\n");
+
+        let result = VM::new()
+            .with_stack_size(16)
+            .with_error_writer(Output::default())
+            .interpret(source.to_string().into_bytes());
+        let error = result.unwrap_err();
+        let Some(RUNTIME_ERROR { message, .. }) = error.downcast_ref() else {
+            panic!("expected a runtime error, got {error}");
+        };
+        assert_eq!(message, "Stack overflow.");
+    }
+
+    #[test]
+    fn class_fields_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            "class Pair{} var p = Pair(); p.x = 1; print p.x;"
+                .to_string()
+                .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "1\n");
+
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            "class Pair {} var p = Pair(); p.first = p.second = 2; print Pair, p, p.first + p.second;"
+                .to_string()
+                .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "Pair Pair instance 4\n");
+    }
+
+    #[test]
+    fn class_fields_should_fail() {
+        for source in [
+            "class Pair {} var p = Pair(); print p.missing;",
+            "class Pair {} var p = Pair(1);",
+            "var p = 1; p.x = 2;",
+            "var p = 1; p();",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            assert!(result.is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn function_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            fun add(a, b) {
+                var sum = a + b;
+                return sum;
+            }
+            fun nothing() {}
+            {
+                var a = 1;
+                {
+                    var a = 2;
+                    print add(a, 3);
+                }
+                print a, nothing();
+            }
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "5\n1 nil\n");
+    }
+
+    #[test]
+    fn method_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            class Point {
+                init(x, y) {
+                    this.x = x;
+                    this.y = y;
+                }
+                sum() {
+                    return this.x + this.y;
+                }
+            }
+            var p = Point(1, 2);
+            var sum = p.sum;
+            print p.sum(), sum(), p.init(3, 4).x;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "3 3 3\n");
+    }
+
+    #[test]
+    fn method_should_fail() {
+        for source in [
+            "class Point {} Point().missing();",
+            "class Point { init(x) {} } Point();",
+            "fun f(a) {} f();",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            assert!(result.is_err(), "{source}");
+        }
+
+        for source in [
+            "print this;",
+            "return 1;",
+            "class Point { init() { return 1; } }",
+            "{ var a = a; }",
+            "{ var a; var a; }",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(COMPILE_ERROR { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn inheritance_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            class A {
+                name() { return "A"; }
+                greet() { return "hello from " + this.name(); }
+            }
+            class B < A {
+                name() { return "B via " + super.name(); }
+            }
+            class C < B {
+                greet() {
+                    var greet = super.greet;
+                    return greet() + "!";
+                }
+            }
+            print B().name(), C().greet();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "B via A hello from B via A!\n");
+    }
+
+    #[test]
+    fn inheritance_should_fail() {
+        let result = VM::new().interpret("var A = 1; class B < A {}".to_string().into_bytes());
+        let error = result.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(RUNTIME_ERROR { .. })));
+
+        for source in [
+            "class A < A {}",
+            "class A { f() { return super.f(); } }",
+            "print super.f;",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+            let error = result.unwrap_err();
+            assert!(
+                matches!(error.downcast_ref(), Some(COMPILE_ERROR { .. })),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn closure_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            fun makeCounter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var counter = makeCounter();
+            var other = makeCounter();
+            print counter(), counter(), other(), counter();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "1 2 1 3\n");
+    }
+
+    #[test]
+    fn closure_shared_upvalue_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            var get;
+            var set;
+            {
+                var shared = "before";
+                fun getter() { return shared; }
+                fun setter(value) { shared = value; }
+                get = getter;
+                set = setter;
+                set("open");
+                print get();
+            }
+            set("closed");
+            print get();
+
+            class A { name() { return "A"; } }
+            class B < A {
+                name() {
+                    fun nested() { return this.tag + super.name(); }
+                    return nested;
+                }
+            }
+            var b = B();
+            b.tag = "B>";
+            print b.name()();
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "open\nclosed\nB>A\n");
+    }
+
+    #[test]
+    fn gc_stress_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone()).with_gc_stress(true);
+        let result = vm.interpret(
+            r#"
+            class Node {}
+            var keep = [Node(), {"a": "kept"}];
+            var i = 0;
+            while (i < 50) {
+                var text = "temporary " + "string";
+                var node = Node();
+                node.next = node;
+                node.items = [node, {"self": node}];
+                i = i + 1;
+            }
+            print keep[1]["a"];
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "kept\n");
+        // the class, `keep` and its two items, plus the last iteration's cycle before its collection
+        assert!(vm.heap.len() <= 8, "{}", vm.heap.len());
+    }
+
+    #[test]
+    fn trace_off_should_succeed() {
+        let source = "var i = 0; while (i < 2000) { i = i + 1; } print i;";
+        let run = |trace: bool| {
+            let output = Output::default();
+            let mut vm = VM::new().with_writer(output.clone()).with_trace(trace);
+            assert!(vm.interpret(source.to_string().into_bytes()).is_ok());
+            output.contents()
+        };
+
+        // the trace goes to the writer alongside the program's output, and only when asked for
+        assert_eq!(run(false), "2000\n");
+        let traced = run(true);
+        assert!(traced.contains("| print"), "{traced}");
+        assert!(traced.lines().any(|line| line == "2000"), "{traced}");
+    }
+
+    #[test]
+    fn compare_strings_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            print "a" < "b";
+            print "b" > "a";
+            print "a" < "a";
+            print "Z" < "a";
+            print "ab" > "a";
+            print 1 < 2;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "true\ntrue\nfalse\ntrue\ntrue\ntrue\n");
+    }
+
+    #[test]
+    fn compare_mixed_should_fail() {
+        for source in [
+            "\"a\" < 1;",
+            "1 > \"a\";",
+            "true < false;",
+            "nil > nil;",
+            "[1] < [2];",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<InterpretError>(),
+                Some(RUNTIME_ERROR { message, .. })
+                    if message == "Operands must be two numbers or two strings."
+            ));
+        }
+    }
+
+    #[test]
+    fn trailing_comma_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            fun pair(a, b) { return [a, b,]; }
+            print pair(1, 2,);
+            print {"a": 1,}["a"];
+            print len([],);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "[1, 2]\n1\n0\n");
+    }
+
+    #[test]
+    fn trailing_comma_should_fail() {
+        for source in [
+            "print [1,,];",
+            "print len(,);",
+            "print [,];",
+            "print {\"a\": 1,,};",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref::<InterpretError>(),
+                    Some(COMPILE_ERROR { .. })
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn const_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            const PI = 3.14;
+            fun area(r) {
+                const squared = r * r;
+                return PI * squared;
+            }
+            print PI, area(1);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "3.14 3.14\n");
+    }
+
+    #[test]
+    fn const_should_fail() {
+        for source in [
+            "const PI = 3.14; PI = 1;",
+            "fun f() { const a = 1; a = 2; }",
+            "fun f() { const a = 1; fun g() { a = 2; } }",
+            "const a;",
+        ] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref::<InterpretError>(),
+                    Some(COMPILE_ERROR { .. })
+                ),
+                "{source}"
+            );
+        }
+
+        // the compiler can't see a global declared in an earlier run, like a REPL line
+        let mut vm = VM::new();
+        assert!(vm
+            .interpret("const PI = 3.14;".to_string().into_bytes())
+            .is_ok());
+        for source in ["PI = 1;", "var PI = 1;"] {
+            let result = vm.interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref::<InterpretError>(),
+                    Some(RUNTIME_ERROR { .. })
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn math_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            assert sqrt(9) == 3;
+            assert max(1, 2) == 2;
+            assert min(1, 2) == 1;
+            assert abs(-5) == 5;
+            assert floor(1.5) == 1;
+            assert ceil(1.5) == 2;
+            print floor(-1.5), ceil(-1.5);
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "-2 -1\n");
+    }
+
+    #[test]
+    fn math_should_fail() {
+        for source in ["abs(nil);", "max(1, \"2\");", "sqrt(true);"] {
+            let result = VM::new().interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref::<InterpretError>(),
+                    Some(RUNTIME_ERROR { message, .. }) if message.starts_with("Expected a number")
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn stdlib_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        let result = vm.interpret(
+            r#"
+            assert cos(0) == 1;
+            assert sin(0) == 0;
+            assert pow(2, 10) == 1024;
+            assert floor(E * 1000) == 2718;
+            print PI;
+            "#
+            .to_string()
+            .into_bytes(),
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "3.141593\n");
+    }
+
+    #[test]
+    fn stdlib_should_fail() {
+        let mut vm = VM::new().with_stdlib(false);
+        for source in ["cos(0);", "print PI;", "abs(1);"] {
+            let result = vm.interpret(source.to_string().into_bytes());
+
+            let error = result.unwrap_err();
+            assert!(
+                matches!(
+                    error.downcast_ref::<InterpretError>(),
+                    Some(RUNTIME_ERROR { message, .. }) if message.starts_with("Undefined variable")
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn runtime_error_instruction_should_fail() {
+        let errors = Output::default();
+        let mut vm = VM::new().with_error_writer(errors.clone());
+        let result = vm.interpret("var a = 1;\nprint 1 + \"x\";".to_string().into_bytes());
+
+        assert!(result.is_err());
+        assert_eq!(
+            errors.contents(),
+            "Operands must be either addable or concatenatable.\n\
+             0008    2 add\n\
+             [line 2] in script\n"
+        );
+    }
+
+    #[test]
+    fn run_assembled_chunk_should_succeed() {
+        let chunk = Chunk::assemble(
+            r#"
+            OP_CONSTANT 0
+            OP_DEFINE_GLOBAL "i"
+            OP_GET_GLOBAL "i"   # loop start, offset 4
+            OP_CONSTANT 3
+            less
+            OP_JUMP_IF_FALSE 16
+            OP_POP
+            OP_GET_GLOBAL "i"
+            OP_PRINT 1
+            OP_GET_GLOBAL "i"
+            OP_CONSTANT 1
+            add
+            OP_SET_GLOBAL "i"
+            OP_POP
+            OP_LOOP 24
+            OP_POP
+            OP_RETURN
+            "#,
+        )
+        .unwrap();
+
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone());
+        assert!(vm.run_chunk(chunk).is_ok());
+        assert_eq!(output.contents(), "0\n1\n2\n");
+    }
+
+    #[test]
+    fn json_diagnostics_should_fail() {
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_json_diagnostics(true);
+
+        let result = vm.interpret("var = 1;\nprint \"a\" +;".to_string().into_bytes());
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(COMPILE_ERROR { line: 1, .. })
+        ));
+        assert_eq!(
+            output.contents(),
+            "[{\"line\": 1, \"column\": 5, \"severity\": \"error\", \"message\": \"Expected variable name.\"}, \
+             {\"line\": 2, \"column\": 12, \"severity\": \"error\", \"message\": \"Expected expression.\"}]\n"
+        );
+
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_json_diagnostics(true);
+        assert!(vm.interpret("print 1;".to_string().into_bytes()).is_ok());
+        assert_eq!(output.contents(), "[]\n1\n");
+    }
+
+    #[test]
+    fn json_trace_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new().with_writer(output.clone()).with_json_trace(true);
+        assert!(vm.interpret("1 < 2".to_string().into_bytes()).is_ok());
+
+        let trace = output.contents();
+        let steps = trace.lines().collect::<Vec<_>>();
+        assert_eq!(steps[0], r#"{"ip": 0, "op": "constant", "stack": []}"#);
+        assert_eq!(steps[2], r#"{"ip": 4, "op": "less", "stack": [1, 2]}"#);
+        assert_eq!(steps[3], r#"{"ip": 5, "op": "return", "stack": [true]}"#);
+
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+    }
+
+    #[test]
+    fn arithmetic_operands_should_fail() {
+        for (source, expected) in [
+            ("-true;", "Operand must be a number."),
+            ("-\"a\";", "Operand must be a number."),
+            ("1 - \"a\";", "Operands must be numbers."),
+            ("true * 2;", "Operands must be numbers."),
+            ("nil + 1;", "Operand must be a number (got nil)."),
+            ("1 - nil;", "Operand must be a number (got nil)."),
+            ("nil * 2;", "Operand must be a number (got nil)."),
+            ("2 / nil;", "Operand must be a number (got nil)."),
+            ("-nil;", "Operand must be a number (got nil)."),
+            ("var a; print a + 1;", "Operand must be a number (got nil)."),
+        ] {
+            let errors = Output::default();
+            let mut vm = VM::new().with_error_writer(errors.clone());
+            let error = vm.interpret(source.to_string().into_bytes()).unwrap_err();
+
+            // reported like any runtime error instead of escaping as a bare anyhow error
+            let Some(RUNTIME_ERROR { message, line }) = error.downcast_ref() else {
+                panic!("expected a runtime error for {source}, got {error}");
+            };
+            assert_eq!(message, expected);
+            assert_eq!(*line, 1);
+            assert!(errors.contents().starts_with(expected), "{source}");
+        }
+    }
+
+    #[test]
+    fn reset_stack_should_succeed() {
+        let output = Output::default();
+        let mut vm = VM::new()
+            .with_writer(output.clone())
+            .with_error_writer(Output::default());
+
+        let result = vm.interpret(
+            "fun f(a) { var b = [a]; return -b; } print f(1);"
+                .to_string()
+                .into_bytes(),
+        );
+        assert!(result.is_err());
+        assert_eq!(vm.sp(), 0);
+        assert!(vm.frames.is_empty());
+        assert!(vm.stack_slice().iter().all(Option::is_none));
+
+        let result = vm.interpret("var a = 1; print a + 2;".to_string().into_bytes());
+        assert!(result.is_ok());
+        assert_eq!(output.contents(), "3\n");
     }
 }
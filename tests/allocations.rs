@@ -0,0 +1,61 @@
+//! Counts heap allocations with a global allocator, which would replace the allocator of every
+//! other test in the same binary, so this gets a test binary of its own.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use rlox::chunk::Chunk;
+use rlox::compiler::scanner::Scanner;
+use rlox::compiler::Compiler;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Counts the current thread's heap allocations, tests running in parallel don't interfere.
+fn count(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+#[test]
+fn compile_allocations_should_succeed() {
+    let source = "var total = 0; fun add(a, b) { return a + b; } \
+                  while (total < 10) { total = add(total, -1 * -2) + (3 - 2) * 1; } \
+                  print total == 10, !nil;"
+        .repeat(8);
+
+    let scanning = count(|| {
+        let mut scanner = Scanner::new(source.clone().into_bytes());
+        while scanner.scan_token().is_some() {}
+    });
+    let compiling = count(|| {
+        let mut chunk = Chunk::default();
+        assert!(Compiler::new(&mut chunk).compile(source.clone().into_bytes()));
+    });
+
+    // tokens used to own their lexeme and were cloned on every advance, `unary`, `binary` and
+    // parse step, so this source took 2079 allocations on top of scanning, now 343
+    let own = compiling - scanning;
+    assert!(
+        own < 700,
+        "compiling allocated {own} times on top of scanning"
+    );
+}
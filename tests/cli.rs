@@ -0,0 +1,198 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rlox-{}-{name}.lox", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn rlox(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn dump_tokens_should_succeed() {
+    let path = script("dump-tokens", "var a = 1;\nprint a;");
+    let output = rlox(&["--dump-tokens", path.to_str().unwrap()], "");
+    std::fs::remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().map(str::trim).collect::<Vec<_>>();
+    assert_eq!(
+        lines,
+        vec![
+            "1:1    TOKEN_VAR \"var\"",
+            "1:5    TOKEN_IDENTIFIER \"a\"",
+            "1:7    TOKEN_EQUAL \"=\"",
+            "1:9    TOKEN_NUMBER \"1\"",
+            "1:10   TOKEN_SEMICOLON \";\"",
+            "2:1    TOKEN_PRINT \"print\"",
+            "2:7    TOKEN_IDENTIFIER \"a\"",
+            "2:8    TOKEN_SEMICOLON \";\"",
+            "2:9    TOKEN_EOF \"\"",
+        ]
+    );
+}
+
+#[test]
+fn dump_tokens_stdin_should_succeed() {
+    let output = rlox(&["--dump-tokens"], "nil");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("TOKEN_NIL \"nil\""));
+}
+
+#[test]
+fn dump_tokens_should_fail() {
+    let output = rlox(&["--dump-tokens"], "\"unterminated");
+
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn dump_bytecode_should_succeed() {
+    let output = rlox(&["--dump-bytecode"], "var a = 1; print a + 2;");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(" add\n"));
+    assert!(stdout.contains(" print "));
+    assert!(!stdout.contains("[ "), "the chunk should not run");
+}
+
+#[test]
+fn dump_bytecode_should_fail() {
+    let output = rlox(&["--dump-bytecode"], "print 1 +;");
+
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn check_should_succeed() {
+    let path = script("check-clean", "var a = 1;\nprint a + 2;");
+    let output = rlox(&["--check", path.to_str().unwrap()], "");
+    std::fs::remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "",
+        "the script should not run"
+    );
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn check_should_fail() {
+    let path = script("check-errors", "print 1 +;\nvar = 2;\nprint \"never\";");
+    let output = rlox(&["--check", path.to_str().unwrap()], "");
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    // every error is reported, against the file it came from
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let name = path.to_str().unwrap();
+    assert!(stderr.contains(&format!("[{name}:1:")), "{stderr}");
+    assert!(stderr.contains(&format!("[{name}:2:")), "{stderr}");
+
+    let output = rlox(&["--check"], "print 1 +;");
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn print_string_should_succeed() {
+    let path = script("print-string", "print \"hi\";\nprint \"a\", \"b\";");
+    let output = rlox(&[path.to_str().unwrap()], "");
+    std::fs::remove_file(path).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "hi\na b\n");
+}
+
+#[test]
+fn eval_should_succeed() {
+    for flag in ["-e", "--eval"] {
+        let output = rlox(&[flag, "print 1+1;"], "");
+
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "2\n");
+    }
+}
+
+#[test]
+fn eval_should_fail() {
+    let output = rlox(&["--eval"], "");
+
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn run_file_exit_code_should_fail() {
+    for (name, source, code) in [
+        ("runtime-error", "print 1;\nprint -nil;", 70),
+        ("compile-error", "print 1 +;", 65),
+    ] {
+        let path = script(name, source);
+        let output = rlox(&[path.to_str().unwrap()], "");
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(output.status.code(), Some(code), "{source}");
+    }
+}
+
+#[test]
+fn eval_exit_code_should_fail() {
+    let output = rlox(&["-e", "print 1; print -nil;"], "");
+
+    assert_eq!(output.status.code(), Some(70));
+    // output before the error is kept
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Operand must be a number (got nil)."));
+
+    let output = rlox(&["-e", "print 1 +;"], "");
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn run_file_source_name_should_fail() {
+    for (name, source, expected) in [
+        (
+            "named-runtime-error",
+            "print 1;\nprint -nil;",
+            ":2] in script",
+        ),
+        (
+            "named-compile-error",
+            "print 1;\n  print 1 +;",
+            ":2:12] Error",
+        ),
+    ] {
+        let path = script(name, source);
+        let output = rlox(&[path.to_str().unwrap()], "");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let location = format!("[{}{expected}", path.display());
+        std::fs::remove_file(path).unwrap();
+        assert!(stderr.contains(&location), "{stderr}");
+    }
+}
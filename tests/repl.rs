@@ -0,0 +1,41 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn run_repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+#[test]
+fn repl_multiline_should_succeed() {
+    let (stdout, stderr) =
+        run_repl("var total = 0;\nwhile (total < 2) {\ntotal = total + 1;\n}\nprint total;\n");
+
+    assert!(stderr.is_empty(), "{stderr}");
+    assert!(stdout.contains(". "));
+    assert!(stdout.lines().any(|line| line.ends_with("2")));
+}
+
+#[test]
+fn repl_blank_line_should_fail() {
+    let (_, stderr) = run_repl("while (false) {\n\nprint 1;\n");
+
+    assert!(stderr.contains("Error"));
+}
@@ -0,0 +1,43 @@
+use rlox::chunk::Chunk;
+use rlox::op_code::OpCode;
+use rlox::value::Value;
+use rlox::vm::VM;
+
+#[test]
+fn stack_slice_should_succeed() {
+    let chunk = Chunk::assemble(
+        "OP_CONSTANT 1
+         OP_CONSTANT 2
+         OP_CONSTANT 3
+         OP_MULTIPLY
+         OP_RETURN",
+    )
+    .unwrap();
+
+    let mut vm = VM::new();
+    vm.run_chunk(chunk).unwrap();
+
+    assert_eq!(vm.sp(), 2);
+    assert_eq!(
+        vm.stack_slice()[..vm.sp()],
+        [Some(Value::VAL_NUMBER(1.)), Some(Value::VAL_NUMBER(6.))]
+    );
+    assert!(vm.stack_slice()[vm.sp()..].iter().all(Option::is_none));
+}
+
+#[test]
+fn run_chunk_should_fail() {
+    let mut popped_empty = Chunk::default();
+    popped_empty.write(OpCode::OP_POP.into(), 1);
+    popped_empty.write(OpCode::OP_RETURN.into(), 1);
+
+    let mut number_name = Chunk::default();
+    let constant = number_name.add_constant(Value::VAL_NUMBER(1.));
+    number_name.write(OpCode::OP_GET_GLOBAL.into(), 1);
+    number_name.write(constant as u8, 1);
+    number_name.write(OpCode::OP_RETURN.into(), 1);
+
+    for chunk in [popped_empty, number_name] {
+        assert!(VM::new().run_chunk(chunk).is_err());
+    }
+}